@@ -12,8 +12,7 @@ pub fn close<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
         .find(|w| w.is_focused)
         .context("No window focused")?;
 
-    if let Some(index) = ctx.state.windows.iter().position(|w| w.id == focused.id) {
-        ctx.state.windows.remove(index);
+    if ctx.state.remove_window(focused.id).is_some() {
         save_state(&ctx.state, &ctx.cache_dir)?;
     }
 
@@ -46,8 +45,9 @@ mod tests_close {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mut ctx = Ctx {
             state,
@@ -59,7 +59,7 @@ mod tests_close {
         close(&mut ctx).expect("Close failed");
 
         assert!(
-            ctx.state.windows.is_empty(),
+            ctx.state.windows(1).is_empty(),
             "Window was not removed from state"
         );
 
@@ -86,8 +86,9 @@ mod tests_close {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mut ctx = Ctx {
             state,
@@ -98,8 +99,8 @@ mod tests_close {
 
         close(&mut ctx).expect("Close failed");
 
-        assert_eq!(ctx.state.windows.len(), 1);
-        assert_eq!(ctx.state.windows[0].id, 10);
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        assert_eq!(ctx.state.windows(1)[0].id, 10);
 
         // CloseWindow action still sent
         assert!(
@@ -5,7 +5,9 @@ use crate::state::save_state;
 use anyhow::Result;
 
 pub fn toggle_flip<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
-    ctx.state.is_flipped = !ctx.state.is_flipped;
+    let current_ws = ctx.socket.get_active_workspace()?.id;
+    let sidebar = ctx.state.sidebar_mut(current_ws);
+    sidebar.is_flipped = !sidebar.is_flipped;
     save_state(&ctx.state, &ctx.cache_dir)?;
     reorder(ctx)?;
     Ok(())
@@ -34,6 +36,7 @@ mod tests {
             height: 500,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -41,10 +44,11 @@ mod tests {
             height: 500,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-        state.is_flipped = false;
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
+        state.sidebar_mut(1).is_flipped = false;
 
         let mut ctx = Ctx {
             state,
@@ -54,7 +58,7 @@ mod tests {
         };
 
         toggle_flip(&mut ctx).expect("Toggle flip failed");
-        assert!(ctx.state.is_flipped);
+        assert!(ctx.state.sidebar(1).unwrap().is_flipped);
 
         // Check reorder happened:
         // Normally (Unflipped): Index 0 is bottom, Index 1 is top.
@@ -1,18 +1,117 @@
 use crate::niri::NiriClient;
-use crate::state::WindowState;
+use crate::state::{WindowState, save_state};
 use crate::{Ctx, Direction};
-use anyhow::Result;
+use anyhow::{Result, bail};
 use niri_ipc::Action;
+use std::collections::HashSet;
 
-pub fn focus<C: NiriClient>(ctx: &mut Ctx<C>, direction: Direction) -> Result<()> {
-    let len = ctx.state.windows.len();
+/// Which sidebar window `focus` moves to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusTarget {
+    /// Cycle through the windows in stacking order
+    Cycle(Direction),
+    /// The window in a slot, counted from 1 at the bottom
+    Index(usize),
+    /// The sidebar window focused before the current one, or the last focused one
+    /// if focus is outside the sidebar
+    Last,
+}
 
-    if len == 0 {
+pub fn focus<C: NiriClient>(ctx: &mut Ctx<C>, target: FocusTarget) -> Result<()> {
+    let current_ws = ctx.socket.get_active_workspace()?.id;
+    // Skip windows waiting to be re-adopted after a niri restart
+    let live_ids: HashSet<u64> = ctx.socket.get_windows()?.iter().map(|w| w.id).collect();
+
+    let id = match target {
+        FocusTarget::Cycle(direction) => {
+            let windows: Vec<&WindowState> = ctx
+                .state
+                .windows(current_ws)
+                .iter()
+                .filter(|w| live_ids.contains(&w.id))
+                .collect();
+            let active_window = ctx.socket.get_active_window()?.id;
+            match cycle(&windows, active_window, direction) {
+                Some(id) => id,
+                None => return Ok(()),
+            }
+        }
+        FocusTarget::Index(index) => match ctx
+            .state
+            .sidebar(current_ws)
+            .and_then(|s| Some(&s.windows[s.slot_index(index)?]))
+        {
+            Some(window) if live_ids.contains(&window.id) => window.id,
+            Some(_) => bail!("The window in slot {} is not open anymore", index),
+            None => bail!("There is no window in slot {} of the sidebar", index),
+        },
+        FocusTarget::Last => {
+            let active_window = ctx.socket.get_active_window()?.id;
+            let history = ctx
+                .state
+                .sidebar(current_ws)
+                .map(|s| s.focus_history.as_slice())
+                .unwrap_or_default();
+            match history
+                .iter()
+                .find(|id| **id != active_window && live_ids.contains(id))
+            {
+                Some(id) => *id,
+                None => return Ok(()),
+            }
+        }
+    };
+
+    let _ = ctx.socket.send_action(Action::FocusWindow { id });
+    Ok(())
+}
+
+/// Jumps from the sidebar back to the window focus came from, or from there into the sidebar
+pub fn focus_toggle<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
+    let windows = ctx.socket.get_windows()?;
+    let is_live = |id: u64| windows.iter().any(|w| w.id == id);
+    // Focus may be on an empty workspace
+    let active_window = windows.iter().find(|w| w.is_focused).map(|w| w.id);
+
+    if active_window.is_some_and(|id| ctx.state.is_tracked(id)) {
+        let action = match ctx.state.main_window {
+            Some(id) if is_live(id) && !ctx.state.is_tracked(id) => Action::FocusWindow { id },
+            _ => Action::FocusTiling {},
+        };
+        let _ = ctx.socket.send_action(action);
         return Ok(());
     }
 
-    let active_window = ctx.socket.get_active_window()?.id;
-    let current_index_opt = ctx.state.windows.iter().position(|w| w.id == active_window);
+    // Also remembered here for when no daemon is tracking focus
+    if let Some(id) = active_window
+        && ctx.state.main_window != Some(id)
+    {
+        ctx.state.main_window = Some(id);
+        save_state(&ctx.state, &ctx.cache_dir)?;
+    }
+
+    let current_ws = ctx.socket.get_active_workspace()?.id;
+    let last_focused = ctx
+        .state
+        .sidebar(current_ws)
+        .and_then(|s| s.last_focused())
+        .filter(|id| is_live(*id));
+    match last_focused {
+        Some(id) => {
+            let _ = ctx.socket.send_action(Action::FocusWindow { id });
+            Ok(())
+        }
+        None => focus(ctx, FocusTarget::Cycle(Direction::Next)),
+    }
+}
+
+fn cycle(windows: &[&WindowState], active_window: u64, direction: Direction) -> Option<u64> {
+    let len = windows.len();
+    if len == 0 {
+        return None;
+    }
+
+    let current_index_opt = windows.iter().position(|w| w.id == active_window);
 
     let next_index = if let Some(i) = current_index_opt {
         match direction {
@@ -26,11 +125,7 @@ pub fn focus<C: NiriClient>(ctx: &mut Ctx<C>, direction: Direction) -> Result<()
         }
     };
 
-    if let Some(WindowState { id, .. }) = ctx.state.windows.get(next_index) {
-        let _ = ctx.socket.send_action(Action::FocusWindow { id: *id });
-    }
-
-    Ok(())
+    windows.get(next_index).map(|w| w.id)
 }
 
 #[cfg(test)]
@@ -38,7 +133,7 @@ mod tests_focus {
     use super::*;
     use crate::Direction;
     use crate::state::AppState;
-    use crate::test_utils::{MockNiri, mock_config, mock_window};
+    use crate::test_utils::{MockNiri, mock_config, mock_ctx, mock_window, mock_window_state};
     use niri_ipc::Action;
     use tempfile::tempdir;
 
@@ -60,6 +155,7 @@ mod tests_focus {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -67,6 +163,7 @@ mod tests_focus {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
         let w3 = WindowState {
             id: 3,
@@ -74,10 +171,11 @@ mod tests_focus {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-        state.windows.push(w3);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
+        state.sidebar_mut(1).windows.push(w3);
 
         let mut ctx = Ctx {
             state,
@@ -86,7 +184,7 @@ mod tests_focus {
             cache_dir: temp_dir.path().to_path_buf(),
         };
 
-        focus(&mut ctx, Direction::Next).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Next)).unwrap();
 
         assert!(
             ctx.socket
@@ -114,6 +212,7 @@ mod tests_focus {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -121,6 +220,7 @@ mod tests_focus {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
         let w3 = WindowState {
             id: 3,
@@ -128,10 +228,11 @@ mod tests_focus {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-        state.windows.push(w3);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
+        state.sidebar_mut(1).windows.push(w3);
 
         let mut ctx = Ctx {
             state,
@@ -140,7 +241,7 @@ mod tests_focus {
             cache_dir: temp_dir.path().to_path_buf(),
         };
 
-        focus(&mut ctx, Direction::Prev).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Prev)).unwrap();
 
         assert!(
             ctx.socket
@@ -169,6 +270,7 @@ mod tests_focus {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -176,9 +278,10 @@ mod tests_focus {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let mut ctx = Ctx {
             state,
@@ -188,7 +291,7 @@ mod tests_focus {
         };
 
         // Next (Should focus last item -> B)
-        focus(&mut ctx, Direction::Next).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Next)).unwrap();
         assert!(
             ctx.socket
                 .sent_actions
@@ -199,7 +302,7 @@ mod tests_focus {
         ctx.socket.sent_actions.clear();
 
         // Prev (Should focus first item -> A)
-        focus(&mut ctx, Direction::Prev).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Prev)).unwrap();
         assert!(
             ctx.socket
                 .sent_actions
@@ -222,9 +325,121 @@ mod tests_focus {
             cache_dir: temp_dir.path().to_path_buf(),
         };
 
-        focus(&mut ctx, Direction::Next).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Next)).unwrap();
         assert!(ctx.socket.sent_actions.is_empty());
-        focus(&mut ctx, Direction::Prev).unwrap();
+        focus(&mut ctx, FocusTarget::Cycle(Direction::Prev)).unwrap();
+        assert!(ctx.socket.sent_actions.is_empty());
+    }
+
+    fn sidebar_ctx(temp_dir: &tempfile::TempDir, focused: u64) -> Ctx<MockNiri> {
+        let windows = [1, 2, 3, 99]
+            .into_iter()
+            .map(|id| mock_window(id, id == focused, id != 99, 1, Some((1.0, 2.0))))
+            .collect();
+
+        let mut state = AppState::default();
+        for id in [1, 2, 3] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 100,
+                height: 100,
+                is_floating: true,
+                position: Some((1.0, 2.0)),
+                ..mock_window_state(id)
+            });
+        }
+
+        mock_ctx(temp_dir.path(), state, windows)
+    }
+
+    #[test]
+    fn test_focus_index_counts_from_the_bottom() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = sidebar_ctx(&temp_dir, 99);
+
+        focus(&mut ctx, FocusTarget::Index(3)).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 3 }]
+        ));
+
+        assert!(focus(&mut ctx, FocusTarget::Index(4)).is_err());
+        // Sent over the control socket the index is not range checked
+        assert!(focus(&mut ctx, FocusTarget::Index(0)).is_err());
+
+        // Flipped sidebars show the end of the list at the bottom
+        ctx.socket.sent_actions.clear();
+        ctx.state.sidebar_mut(1).is_flipped = true;
+        focus(&mut ctx, FocusTarget::Index(3)).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 1 }]
+        ));
+    }
+
+    #[test]
+    fn test_focus_last_walks_back_through_history() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = sidebar_ctx(&temp_dir, 2);
+
+        // Nothing was focused yet
+        focus(&mut ctx, FocusTarget::Last).unwrap();
         assert!(ctx.socket.sent_actions.is_empty());
+
+        ctx.state.sidebar_mut(1).record_focus(3);
+        ctx.state.sidebar_mut(1).record_focus(2);
+
+        // The current window is skipped, so focus goes back to the one before it
+        focus(&mut ctx, FocusTarget::Last).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 3 }]
+        ));
+    }
+
+    #[test]
+    fn test_focus_toggle_between_main_window_and_sidebar() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = sidebar_ctx(&temp_dir, 99);
+        ctx.state.sidebar_mut(1).record_focus(2);
+
+        // From the main layout into the last focused sidebar window
+        focus_toggle(&mut ctx).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 2 }]
+        ));
+        assert_eq!(ctx.state.main_window, Some(99));
+
+        // And back to the window it came from
+        let mut ctx = Ctx {
+            state: std::mem::take(&mut ctx.state),
+            ..sidebar_ctx(&temp_dir, 2)
+        };
+        focus_toggle(&mut ctx).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 99 }]
+        ));
+    }
+
+    #[test]
+    fn test_focus_toggle_without_history() {
+        let temp_dir = tempdir().unwrap();
+
+        // Nothing focused in the sidebar yet, enter it at the top of the stack
+        let mut ctx = sidebar_ctx(&temp_dir, 99);
+        focus_toggle(&mut ctx).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusWindow { id: 3 }]
+        ));
+
+        // No known main window, fall back to the tiling layout
+        let mut ctx = sidebar_ctx(&temp_dir, 1);
+        focus_toggle(&mut ctx).unwrap();
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::FocusTiling {}]
+        ));
     }
 }
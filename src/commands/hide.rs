@@ -5,7 +5,9 @@ use crate::state::save_state;
 use anyhow::Result;
 
 pub fn toggle_visibility<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
-    ctx.state.is_hidden = !ctx.state.is_hidden;
+    let current_ws = ctx.socket.get_active_workspace()?.id;
+    let sidebar = ctx.state.sidebar_mut(current_ws);
+    sidebar.is_hidden = !sidebar.is_hidden;
     save_state(&ctx.state, &ctx.cache_dir)?;
     reorder(ctx)?;
     Ok(())
@@ -32,9 +34,10 @@ mod tests {
             height: 500,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.is_hidden = false;
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).is_hidden = false;
 
         let mut ctx = Ctx {
             state,
@@ -46,7 +49,7 @@ mod tests {
         toggle_visibility(&mut ctx).expect("Toggle visibility failed");
 
         // State changed and Move action sent (Moved to Hidden X)
-        assert!(ctx.state.is_hidden);
+        assert!(ctx.state.sidebar(1).unwrap().is_hidden);
 
         // Screen width 1920. Peek is 10 (focused). Target X should be 1920 - 10 = 1910.
         let actions = &ctx.socket.sent_actions;
@@ -63,7 +66,7 @@ mod tests {
         toggle_visibility(&mut ctx).expect("Toggle visibility failed");
 
         // State changed back and Move action sent (Moved to Visible X)
-        assert!(!ctx.state.is_hidden);
+        assert!(!ctx.state.sidebar(1).unwrap().is_hidden);
         // Visible X = 1920 - 300 (width) - 20 (margin) = 1600
         let actions = &ctx.socket.sent_actions;
         dbg!(actions);
@@ -1,82 +1,280 @@
 use crate::commands::movefrom::move_to;
-use crate::commands::reorder;
-use crate::commands::togglewindow::add_to_sidebar;
-use crate::config::load_config;
+use crate::commands::togglewindow::{
+    add_to_sidebar, fit_to_sidebar, refit_sidebar_windows, remove_from_sidebar, window_workspace,
+};
+use crate::commands::{reorder, reorder_refitted, waybar_status};
+use crate::config::{self, Config};
+use crate::control::{self, ClientMessage, Reply, Request};
 use crate::niri::connect;
-use crate::state::{get_default_cache_dir, load_state, save_state};
-use crate::window_rules::resolve_auto_add;
+use crate::state::{AppState, WindowIdentity, lock_instance, save_state};
+use crate::window_rules::{Eject, RuleTarget, resolve_rule};
 use crate::{Ctx, NiriClient};
-use anyhow::Result;
-use fslock::LockFile;
+use anyhow::{Result, bail};
 use niri_ipc::socket::Socket;
-use niri_ipc::{Event, Request, Window};
+use niri_ipc::{Event, Window};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Everything the daemon reacts to, funneled into a single queue so the
+/// state is only ever touched from the main loop
+enum Message {
+    Event(Event),
+    Request(Request, Sender<Reply>),
+    Subscribe(UnixStream),
+    ConfigChanged,
+    EventStreamClosed,
+}
 
-pub fn listen(mut ctx: Ctx<Socket>) -> Result<()> {
-    let _ = ctx.socket.send(Request::EventStream)?;
-    let mut read_event = ctx.socket.read_events();
+/// How often the config file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a control client may take to send its request or to take a reply.
+/// Clients are served one at a time, a stalled one must not block the others.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Runs the daemon. Changes to the config file at `config_path` are picked up while it runs,
+/// `strict` rejects changed configs with problems instead of only warning about them.
+pub fn listen(mut ctx: Ctx<Socket>, config_path: PathBuf, strict: bool) -> Result<()> {
+    let socket_path = control::socket_path(&ctx.cache_dir);
+    let listener = bind_control_socket(&socket_path)?;
+
+    let mut event_socket = connect()?;
+    let _ = event_socket.send(niri_ipc::Request::EventStream)?;
+
+    let (tx, rx) = mpsc::channel();
+    spawn_event_reader(event_socket, tx.clone());
+    spawn_config_watcher(config_path.clone(), tx.clone());
+    spawn_control_server(listener, tx);
     println!("niri-sidebar: Listening for window events...");
 
-    while let Ok(event) = read_event() {
-        match event {
-            Event::WindowClosed { id } => handle_close_event(id)?,
-            Event::WindowFocusChanged { .. } => handle_focus_change()?,
-            Event::WorkspaceActivated { id, focused: true } => handle_workspace_focus(id)?,
-            Event::WindowOpenedOrChanged { window } => handle_new_window(&window)?,
-            _ => {}
+    // Sidebar windows from before a niri restart get new ids when they open again
+    ctx.state.restore_started = Some(Instant::now());
+    ctx.state.listening = true;
+
+    let mut watchers = Watchers::default();
+    for message in rx {
+        // Losing one message is better than losing the daemon
+        let _lock = match lock_instance(&ctx.cache_dir) {
+            Ok(lock) => lock,
+            Err(e) => {
+                eprintln!("niri-sidebar: Failed to lock the state: {:#}", e);
+                match message {
+                    Message::Request(_, reply) => {
+                        let _ = reply.send(Err(format!("Failed to lock the state: {:#}", e)));
+                    }
+                    Message::EventStreamClosed => break,
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        match message {
+            Message::Event(event) => {
+                if let Err(e) = handle_event(&mut ctx, event) {
+                    eprintln!("niri-sidebar: Failed to handle event: {:#}", e);
+                }
+            }
+            Message::Request(request, reply) => {
+                let result = control::dispatch(&mut ctx, request).map_err(|e| format!("{:#}", e));
+                let _ = reply.send(result);
+            }
+            Message::Subscribe(stream) => watchers.add(stream),
+            Message::ConfigChanged => {
+                if let Err(e) = reload_config(&mut ctx, &config_path, strict) {
+                    eprintln!("niri-sidebar: Keeping the previous config: {:#}", e);
+                }
+            }
+            Message::EventStreamClosed => break,
         }
+        watchers.notify(&mut ctx);
     }
 
+    let _ = fs::remove_file(&socket_path);
     Ok(())
 }
 
-fn get_ctx() -> Result<(Ctx<Socket>, LockFile)> {
-    let cache_dir = get_default_cache_dir()?;
-    let mut lock_path = cache_dir.clone();
-    lock_path.push("instance.lock");
-    let mut lock_file = LockFile::open(&lock_path)?;
-    lock_file.lock()?;
-
-    let state = load_state(&cache_dir)?;
-    let config = load_config();
-    let ctx = Ctx {
-        state,
-        config,
-        socket: connect()?,
-        cache_dir,
-    };
+fn bind_control_socket(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("Another niri-sidebar daemon is already running");
+        }
+        // Left behind by a daemon that did not shut down cleanly
+        fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+fn spawn_event_reader(socket: Socket, tx: Sender<Message>) {
+    thread::spawn(move || {
+        let mut read_event = socket.read_events();
+        while let Ok(event) = read_event() {
+            if tx.send(Message::Event(event)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Message::EventStreamClosed);
+    });
+}
 
-    Ok((ctx, lock_file))
+fn spawn_control_server(listener: UnixListener, tx: Sender<Message>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle_client(&stream, &tx) {
+                eprintln!("niri-sidebar: Failed to handle request: {:#}", e);
+            }
+        }
+    });
 }
 
-fn handle_close_event(closed_id: u64) -> Result<()> {
-    let (mut ctx, _lock) = get_ctx()?;
-    process_close(&mut ctx, closed_id)
+fn spawn_config_watcher(path: PathBuf, tx: Sender<Message>) {
+    thread::spawn(move || {
+        let mut watcher = ConfigWatcher::new(path);
+        loop {
+            thread::sleep(CONFIG_POLL_INTERVAL);
+            if watcher.changed() && tx.send(Message::ConfigChanged).is_err() {
+                return;
+            }
+        }
+    });
 }
 
-fn handle_focus_change() -> Result<()> {
-    let (mut ctx, _lock) = get_ctx()?;
-    process_focus(&mut ctx)
+/// Notices changes to the config file by polling its modification time
+struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
 }
 
-fn handle_workspace_focus(ws_id: u64) -> Result<()> {
-    let (mut ctx, _lock) = get_ctx()?;
-    if ctx.config.interaction.sticky {
-        process_move(&mut ctx, ws_id)
+impl ConfigWatcher {
+    fn new(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        ConfigWatcher { path, modified }
+    }
+
+    /// Whether the file was written, created or removed since the last call
+    fn changed(&mut self) -> bool {
+        let modified = modified_time(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Loads the config file at `path` and applies it, a removed file brings back the defaults.
+/// On errors the current config stays in place.
+fn reload_config<C: NiriClient>(ctx: &mut Ctx<C>, path: &Path, strict: bool) -> Result<()> {
+    let config = if path.exists() {
+        config::read_config(path, strict)?
     } else {
-        Ok(())
+        Config::default()
+    };
+    println!("niri-sidebar: Reloaded config from {}", path.display());
+    apply_config(ctx, config)
+}
+
+/// Swaps in `config` and resizes and moves the sidebar windows to match it
+fn apply_config<C: NiriClient>(ctx: &mut Ctx<C>, config: Config) -> Result<()> {
+    ctx.config = config;
+    refit_sidebar_windows(ctx)?;
+    reorder_refitted(ctx)
+}
+
+fn handle_client(stream: &UnixStream, tx: &Sender<Message>) -> Result<()> {
+    // Also keeps a stalled status bar from blocking the daemon
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let request = match control::read_message(stream)? {
+        ClientMessage::Request(request) => request,
+        ClientMessage::Watch => {
+            let stream = stream.try_clone()?;
+            if tx.send(Message::Subscribe(stream)).is_err() {
+                bail!("Daemon is shutting down");
+            }
+            return Ok(());
+        }
+    };
+    let (reply_tx, reply_rx) = mpsc::channel();
+    if tx.send(Message::Request(request, reply_tx)).is_err() {
+        bail!("Daemon is shutting down");
     }
+    control::write_reply(stream, &reply_rx.recv()?)
 }
 
-fn handle_new_window(window: &Window) -> Result<()> {
-    let (mut ctx, _lock) = get_ctx()?;
-    process_new_window(&mut ctx, window)
+/// Clients of `niri-sidebar watch`, sent a new status line whenever it changes
+#[derive(Default)]
+struct Watchers {
+    streams: Vec<UnixStream>,
+    /// Watchers that still need the current status line
+    pending: Vec<UnixStream>,
+    last_line: Option<String>,
+}
+
+impl Watchers {
+    fn add(&mut self, stream: UnixStream) {
+        self.pending.push(stream);
+    }
+
+    fn notify<C: NiriClient>(&mut self, ctx: &mut Ctx<C>) {
+        if self.streams.is_empty() && self.pending.is_empty() {
+            return;
+        }
+
+        let line = match waybar_status(ctx).and_then(|s| Ok(serde_json::to_string(&s)?)) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("niri-sidebar: Failed to compute status: {:#}", e);
+                return;
+            }
+        };
+        if self.last_line.as_ref() != Some(&line) {
+            self.streams
+                .retain_mut(|stream| writeln!(stream, "{}", line).is_ok());
+        }
+        for mut stream in self.pending.drain(..) {
+            if writeln!(stream, "{}", line).is_ok() {
+                self.streams.push(stream);
+            }
+        }
+        self.last_line = Some(line);
+    }
+}
+
+fn handle_event<C: NiriClient>(ctx: &mut Ctx<C>, event: Event) -> Result<()> {
+    match event {
+        Event::WindowClosed { id } => process_close(ctx, id),
+        Event::WindowFocusChanged { id } => process_focus(ctx, id),
+        Event::WorkspaceActivated { id, focused: true } => {
+            if is_sticky(ctx, id)? {
+                process_move(ctx, id)
+            } else {
+                Ok(())
+            }
+        }
+        Event::WindowOpenedOrChanged { window } => process_new_window(ctx, &window),
+        Event::WindowsChanged { windows } => process_windows_changed(ctx, &windows),
+        _ => Ok(()),
+    }
 }
 
 pub fn process_close<C: NiriClient>(ctx: &mut Ctx<C>, closed_id: u64) -> Result<()> {
-    if let Some(index) = ctx.state.windows.iter().position(|w| w.id == closed_id) {
+    ctx.state.ejected_windows.retain(|id| *id != closed_id);
+    if ctx.state.main_window == Some(closed_id) {
+        ctx.state.main_window = None;
+        save_state(&ctx.state, &ctx.cache_dir)?;
+    }
+
+    if ctx.state.remove_window(closed_id).is_some() {
         println!("Sidebar window {} closed. Reordering...", closed_id);
 
-        ctx.state.windows.remove(index);
         save_state(&ctx.state, &ctx.cache_dir)?;
         dbg!(&ctx.state);
 
@@ -86,18 +284,74 @@ pub fn process_close<C: NiriClient>(ctx: &mut Ctx<C>, closed_id: u64) -> Result<
     Ok(())
 }
 
-pub fn process_focus<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
+pub fn process_focus<C: NiriClient>(ctx: &mut Ctx<C>, focused_id: Option<u64>) -> Result<()> {
+    // Remembered so accordion mode keeps the window expanded after focus leaves the sidebar,
+    // and so `focus --last` can jump back to it
+    if let Some(id) = focused_id
+        && let Some((ws, _)) = ctx.state.find_window(id)
+        && ctx.state.sidebar_mut(ws).record_focus(id)
+    {
+        save_state(&ctx.state, &ctx.cache_dir)?;
+    }
+    // `focus-toggle` goes back to the window focus came from
+    if let Some(id) = focused_id
+        && !ctx.state.is_tracked(id)
+        && ctx.state.main_window != Some(id)
+    {
+        ctx.state.main_window = Some(id);
+        save_state(&ctx.state, &ctx.cache_dir)?;
+    }
+
     reorder(ctx)?;
     Ok(())
 }
 
+/// Whether sidebars follow to workspace `ws_id`, going by the settings of its output
+fn is_sticky<C: NiriClient>(ctx: &mut Ctx<C>, ws_id: u64) -> Result<bool> {
+    let workspaces = ctx.socket.get_workspaces()?;
+    let output = workspaces
+        .iter()
+        .find(|ws| ws.id == ws_id)
+        .and_then(|ws| ws.output.as_deref());
+    Ok(ctx.layout_for(output).interaction.sticky)
+}
+
+/// Brings the sidebars of the other workspaces on the output of `ws_id` along to it
 pub fn process_move<C: NiriClient>(ctx: &mut Ctx<C>, ws_id: u64) -> Result<()> {
+    let workspaces = ctx.socket.get_workspaces()?;
+    let Some(output) = workspaces
+        .iter()
+        .find(|ws| ws.id == ws_id)
+        .and_then(|ws| ws.output.clone())
+    else {
+        return Ok(());
+    };
+    // Sidebars of workspaces that are gone go by the output they were last seen on
+    let on_output = |ws: u64, state: &AppState| match workspaces.iter().find(|w| w.id == ws) {
+        Some(workspace) => workspace.output.as_deref() == Some(output.as_str()),
+        None => state.sidebar(ws).and_then(|s| s.output.as_deref()) == Some(output.as_str()),
+    };
+
     let windows: Vec<_> = ctx.socket.get_windows()?;
     let sidebar_windows = windows
         .iter()
-        .filter(|w| ctx.state.windows.iter().any(|ws| ws.id == w.id))
+        .filter(|w| {
+            ctx.state
+                .find_window(w.id)
+                .is_some_and(|(ws, _)| on_output(ws, &ctx.state))
+        })
         .collect();
     move_to(ctx, sidebar_windows, ws_id)?;
+
+    let merged: Vec<u64> = ctx
+        .state
+        .sidebars
+        .keys()
+        .copied()
+        .filter(|ws| on_output(*ws, &ctx.state))
+        .collect();
+    ctx.state.merge_into(ws_id, |ws| merged.contains(&ws));
+    save_state(&ctx.state, &ctx.cache_dir)?;
     Ok(())
 }
 
@@ -115,10 +369,107 @@ pub fn process_new_window<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> R
         return Ok(());
     }
 
-    if resolve_auto_add(&ctx.config.window_rule, window)
-        && !ctx.state.windows.iter().any(|w| w.id == window.id)
-    {
-        add_to_sidebar(ctx, window)?;
+    // Keep the stored identity current, titles change all the time
+    if let Some((ws, index)) = ctx.state.find_window(window.id) {
+        let identity = WindowIdentity::of(window);
+        let tracked = &mut ctx.state.sidebar_mut(ws).windows[index];
+        if tracked.identity != identity {
+            tracked.identity = identity;
+            save_state(&ctx.state, &ctx.cache_dir)?;
+        }
+        return eject_if_needed(ctx, window.id);
+    }
+
+    let live_ids: HashSet<u64> = ctx.socket.get_windows()?.iter().map(|w| w.id).collect();
+    let adopted = adopt_window(ctx, window, &live_ids)?;
+    let summoned = take_summon(ctx, window);
+    if adopted || summoned || auto_add(ctx, window)? {
+        if !ctx.state.is_tracked(window.id) {
+            add_to_sidebar(ctx, window)?;
+        }
+        if summoned && let Some((ws, _)) = ctx.state.find_window(window.id) {
+            ctx.state.sidebar_mut(ws).is_hidden = false;
+        }
+        save_state(&ctx.state, &ctx.cache_dir)?;
+        reorder(ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Re-evaluates the rules of a sidebar window and pushes it out of the sidebar if they ask for it
+fn eject_if_needed<C: NiriClient>(ctx: &mut Ctx<C>, id: u64) -> Result<()> {
+    // Events can be stale, e.g. still show a window as tiled right before the
+    // sidebar floated it, so look at the current state of the window instead
+    let Some(window) = ctx.socket.get_windows()?.into_iter().find(|w| w.id == id) else {
+        return Ok(());
+    };
+    let workspace = window_workspace(ctx, &window)?;
+    let Some(output) = workspace.output.as_deref() else {
+        return Ok(());
+    };
+    let screen = ctx.socket.get_output_dimensions(output)?;
+    let target = RuleTarget::new(&window, slice::from_ref(&workspace), &ctx.state);
+    let Some(reason) = resolve_rule(&ctx.config.window_rule, &target).eject(&window, screen) else {
+        return Ok(());
+    };
+
+    println!("Ejecting window {} from the sidebar ({:?})", id, reason);
+    match reason {
+        Eject::Rule => remove_from_sidebar(ctx, &window)?,
+        // Restoring the old size and position would undo what was just done to the window
+        Eject::Tiled | Eject::Fullscreen => {
+            ctx.state.remove_window(id);
+            ctx.state.ejected_windows.push(id);
+        }
+    }
+
+    save_state(&ctx.state, &ctx.cache_dir)?;
+    reorder(ctx)?;
+    Ok(())
+}
+
+/// Whether `window` is one `summon` is waiting for, it is not waited for anymore afterwards
+fn take_summon<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> bool {
+    ctx.state
+        .pending_summons
+        .retain(|pending| !pending.is_expired());
+    let Some(index) = window.app_id.as_ref().and_then(|app_id| {
+        ctx.state
+            .pending_summons
+            .iter()
+            .position(|pending| &pending.app_id == app_id)
+    }) else {
+        return false;
+    };
+    ctx.state.pending_summons.remove(index);
+    true
+}
+
+fn auto_add<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Result<bool> {
+    if ctx.state.ejected_windows.contains(&window.id) {
+        return Ok(false);
+    }
+    let workspaces = ctx.socket.get_workspaces()?;
+    let target = RuleTarget::new(window, &workspaces, &ctx.state);
+    Ok(resolve_rule(&ctx.config.window_rule, &target).auto_add())
+}
+
+/// Sent once when the event stream starts, re-adopts sidebar windows that were opened
+/// before the daemon (or niri) restarted and drops the ones that are gone. Windows that
+/// open later are re-adopted by `process_new_window`.
+pub fn process_windows_changed<C: NiriClient>(ctx: &mut Ctx<C>, windows: &[Window]) -> Result<()> {
+    let live_ids: HashSet<u64> = windows.iter().map(|w| w.id).collect();
+
+    let mut adopted = false;
+    for window in windows {
+        if !ctx.state.is_tracked(window.id) && !ctx.state.ignored_windows.contains(&window.id) {
+            adopted |= adopt_window(ctx, window, &live_ids)?;
+        }
+    }
+    let pruned = ctx.state.prune_closed(&live_ids);
+
+    if adopted || pruned {
         save_state(&ctx.state, &ctx.cache_dir)?;
         reorder(ctx)?;
     }
@@ -126,22 +477,76 @@ pub fn process_new_window<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> R
     Ok(())
 }
 
+/// Gives `window` the sidebar slot of a tracked window niri no longer knows about
+/// (niri hands out new ids after a restart), picking the slot whose stored identity
+/// matches best. Returns whether a slot was taken over.
+fn adopt_window<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window: &Window,
+    live_ids: &HashSet<u64>,
+) -> Result<bool> {
+    if !ctx.state.is_restoring() {
+        return Ok(false);
+    }
+    let best = ctx
+        .state
+        .all_windows()
+        .filter(|w| !live_ids.contains(&w.id))
+        .filter_map(|w| Some((w.identity.match_score(window)?, w.id)))
+        // On ties the earliest slot wins, so restored windows keep their order
+        .min_by_key(|(score, _)| std::cmp::Reverse(*score));
+    let Some((_, stale_id)) = best else {
+        return Ok(false);
+    };
+
+    let workspace = window_workspace(ctx, window)?;
+    let workspaces = ctx.socket.get_workspaces()?;
+    if let Some((old_ws, _)) = ctx.state.find_window(stale_id)
+        && !workspaces.iter().any(|ws| ws.id == old_ws)
+    {
+        // The old workspace is gone too, bring the rest of its sidebar along
+        ctx.state.move_sidebar(old_ws, workspace.id);
+    }
+    let Some((old_ws, index)) = ctx.state.find_window(stale_id) else {
+        return Ok(false);
+    };
+    let mut tracked = ctx.state.sidebar_mut(old_ws).windows.remove(index);
+
+    println!("Re-adopting window {} as {}", stale_id, window.id);
+    tracked.id = window.id;
+    tracked.identity = WindowIdentity::of(window);
+    ctx.state.insert_window(workspace.id, index, tracked);
+    for sidebar in ctx.state.sidebars.values_mut() {
+        for id in sidebar
+            .focus_history
+            .iter_mut()
+            .filter(|id| **id == stale_id)
+        {
+            *id = window.id;
+        }
+    }
+
+    fit_to_sidebar(ctx, window, &workspace)?;
+
+    Ok(true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Config, WindowRule};
-    use crate::state::{AppState, WindowState};
-    use crate::test_utils::{MockNiri, mock_window};
+    use crate::config::{Config, WindowMatch, WindowRule};
+    use crate::state::{
+        AppState, PendingSummon, RESTORE_TIMEOUT, SUMMON_TIMEOUT, WindowIdentity, WindowState,
+    };
+    use crate::test_utils::{MockNiri, mock_window, mock_window_state, mock_workspace};
     use niri_ipc::{Action, WorkspaceReferenceArg};
     use regex::Regex;
+    use std::time::Instant;
     use tempfile::tempdir;
 
     #[test]
     fn test_process_close_removes_window_and_reorders() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let mut state = AppState::default();
         let w1 = WindowState {
@@ -150,6 +555,7 @@ mod tests {
             height: 500,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 200,
@@ -157,9 +563,10 @@ mod tests {
             height: 500,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let w100 = mock_window(100, true, true, 1, Some((1.0, 2.0)));
         let w200 = mock_window(200, false, true, 1, Some((1.0, 2.0)));
@@ -175,9 +582,9 @@ mod tests {
         process_close(&mut ctx, 100).expect("Process close failed");
 
         // 100 removed
-        assert!(!ctx.state.windows.iter().any(|w| w.id == 100));
-        assert_eq!(ctx.state.windows.len(), 1);
-        assert_eq!(ctx.state.windows[0].id, 200);
+        assert!(!ctx.state.windows(1).iter().any(|w| w.id == 100));
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        assert_eq!(ctx.state.windows(1)[0].id, 200);
         // Reorder should have run (sending actions)
         assert!(!ctx.socket.sent_actions.is_empty());
     }
@@ -185,9 +592,6 @@ mod tests {
     #[test]
     fn test_process_close_ignores_unknown_window() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let mut state = AppState::default();
         let w1 = WindowState {
@@ -196,8 +600,9 @@ mod tests {
             height: 500,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mock = MockNiri::new(vec![]);
 
@@ -211,8 +616,8 @@ mod tests {
         process_close(&mut ctx, 999).expect("Process close failed");
 
         // State should still have Window 100
-        assert_eq!(ctx.state.windows.len(), 1);
-        assert_eq!(ctx.state.windows[0].id, 100);
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        assert_eq!(ctx.state.windows(1)[0].id, 100);
 
         // No reorder actions should have been sent
         assert!(ctx.socket.sent_actions.is_empty());
@@ -221,9 +626,6 @@ mod tests {
     #[test]
     fn test_process_move_consolidates_tracked_windows_from_all_workspaces() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let mut state = AppState::default();
         let w1 = WindowState {
@@ -232,6 +634,7 @@ mod tests {
             height: 200,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 20,
@@ -239,9 +642,10 @@ mod tests {
             height: 200,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(2).windows.push(w2);
 
         // Window 10: Tracked, on WS 1
         let w10 = mock_window(10, true, false, 1, Some((1.0, 2.0)));
@@ -250,7 +654,9 @@ mod tests {
         // Window 30: Untracked, on WS 1
         let w30 = mock_window(30, true, false, 1, Some((1.0, 2.0)));
 
-        let mock = MockNiri::new(vec![w10, w20, w30]);
+        let mut mock = MockNiri::new(vec![w10, w20, w30]);
+        mock.workspaces.push(mock_workspace(2, "eDP-1", false));
+        mock.workspaces.push(mock_workspace(99, "eDP-1", false));
 
         let mut ctx = Ctx {
             state,
@@ -286,15 +692,138 @@ mod tests {
 
         check_action(&actions[0], 10);
         check_action(&actions[1], 20);
+
+        // Both sidebars are merged into the one on the target workspace
+        assert_eq!(ctx.state.sidebars.len(), 1);
+        let ids: Vec<u64> = ctx.state.windows(target_ws).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![10, 20]);
     }
 
     #[test]
-    fn test_process_new_window_adds_when_autoadd_true() {
+    fn test_process_move_keeps_sidebars_of_other_outputs() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
+
+        let mut state = AppState::default();
+        for (id, ws) in [(10, 1), (30, 3)] {
+            state.sidebar_mut(ws).windows.push(WindowState {
+                width: 100,
+                height: 200,
+                is_floating: true,
+                position: Some((1.0, 2.0)),
+                ..mock_window_state(id)
+            });
         }
 
+        // Workspaces 1 and 2 are on eDP-1, workspace 3 is on DP-1
+        let mut mock = MockNiri::new(vec![
+            mock_window(10, false, true, 1, Some((1.0, 2.0))),
+            mock_window(30, false, true, 3, Some((1.0, 2.0))),
+        ]);
+        mock.workspaces.push(mock_workspace(2, "eDP-1", false));
+        mock.workspaces.push(mock_workspace(3, "DP-1", false));
+        mock.outputs.insert("DP-1".into(), (2560, 1440));
+
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_move(&mut ctx, 2).expect("process_move failed");
+
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::MoveWindowToWorkspace {
+                window_id: Some(10),
+                reference: WorkspaceReferenceArg::Id(2),
+                ..
+            }]
+        ));
+        let ids = |ws| -> Vec<u64> { ctx.state.windows(ws).iter().map(|w| w.id).collect() };
+        assert_eq!(ids(2), vec![10]);
+        assert_eq!(ids(3), vec![30]);
+        assert!(ctx.state.sidebar(1).is_none());
+    }
+
+    #[test]
+    fn test_sticky_is_resolved_with_the_active_profile() {
+        let temp_dir = tempdir().unwrap();
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(10));
+        let mut mock = MockNiri::new(vec![mock_window(10, false, true, 1, None)]);
+        mock.workspaces.push(mock_workspace(2, "eDP-1", false));
+
+        let mut config = Config::default();
+        config.profile.insert(
+            "follow".into(),
+            crate::config::ProfileConfig {
+                interaction: crate::config::InteractionOverride {
+                    sticky: Some(true),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+        let activated = || Event::WorkspaceActivated {
+            id: 2,
+            focused: true,
+        };
+
+        handle_event(&mut ctx, activated()).unwrap();
+        assert!(ctx.socket.sent_actions.is_empty());
+
+        ctx.state.profile = Some("follow".into());
+        handle_event(&mut ctx, activated()).unwrap();
+        assert_eq!(ctx.state.windows(2).len(), 1);
+    }
+
+    #[test]
+    fn test_process_focus_remembers_last_focused_sidebar_window() {
+        let temp_dir = tempdir().unwrap();
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 300,
+            height: 500,
+            ..mock_window_state(100)
+        });
+
+        let w100 = mock_window(100, true, true, 1, Some((1.0, 2.0)));
+        let w200 = mock_window(200, false, false, 1, None);
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![w100, w200]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_focus(&mut ctx, Some(100)).expect("Process focus failed");
+        assert_eq!(ctx.state.sidebar(1).unwrap().focus_history, vec![100]);
+
+        assert_eq!(ctx.state.main_window, None);
+
+        // Focusing a window outside the sidebar keeps the last sidebar window
+        process_focus(&mut ctx, Some(200)).expect("Process focus failed");
+        process_focus(&mut ctx, None).expect("Process focus failed");
+        assert_eq!(ctx.state.sidebar(1).unwrap().focus_history, vec![100]);
+        assert_eq!(ctx.state.main_window, Some(200));
+
+        process_close(&mut ctx, 200).expect("Process close failed");
+        assert_eq!(ctx.state.main_window, None);
+    }
+
+    #[test]
+    fn test_process_new_window_adds_when_autoadd_true() {
+        let temp_dir = tempdir().unwrap();
+
         let state = AppState::default();
 
         let w100 = mock_window(100, true, true, 1, Some((1.0, 2.0)));
@@ -302,8 +831,11 @@ mod tests {
 
         let config = Config {
             window_rule: vec![WindowRule {
-                app_id: Some(Regex::new(r"test").unwrap()),
-                auto_add: true,
+                matches: WindowMatch {
+                    app_id: Some(Regex::new(r"test").unwrap()),
+                    ..Default::default()
+                },
+                auto_add: Some(true),
                 ..Default::default()
             }],
             ..Default::default()
@@ -320,19 +852,73 @@ mod tests {
         process_new_window(&mut ctx, &w100).expect("Process new window failed");
 
         // 100 added
-        assert!(ctx.state.windows.iter().any(|w| w.id == 100));
-        assert_eq!(ctx.state.windows.len(), 1);
-        assert_eq!(ctx.state.windows[0].id, 100);
+        assert!(ctx.state.windows(1).iter().any(|w| w.id == 100));
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        assert_eq!(ctx.state.windows(1)[0].id, 100);
         // Reorder should have run (sending actions)
         assert!(!ctx.socket.sent_actions.is_empty());
     }
 
+    #[test]
+    fn test_process_new_window_adds_summoned_window() {
+        let temp_dir = tempdir().unwrap();
+
+        let mut state = AppState {
+            pending_summons: vec![PendingSummon::new("test")],
+            ..Default::default()
+        };
+        state.sidebar_mut(1).is_hidden = true;
+
+        let w100 = mock_window(100, true, false, 1, None);
+        let w200 = mock_window(200, false, false, 1, None);
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![w100.clone(), w200.clone()]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_new_window(&mut ctx, &w100).expect("Process new window failed");
+
+        assert_eq!(ctx.state.windows(1)[0].id, 100);
+        assert!(!ctx.state.sidebar(1).unwrap().is_hidden);
+        assert!(ctx.state.pending_summons.is_empty());
+
+        // Only the first window of the summoned app is taken
+        process_new_window(&mut ctx, &w200).expect("Process new window failed");
+        assert!(!ctx.state.is_tracked(200));
+    }
+
+    #[test]
+    fn test_process_new_window_ignores_expired_summon() {
+        let temp_dir = tempdir().unwrap();
+
+        let started = Instant::now().checked_sub(SUMMON_TIMEOUT * 2).unwrap();
+        let state = AppState {
+            pending_summons: vec![PendingSummon {
+                app_id: "test".into(),
+                started,
+            }],
+            ..Default::default()
+        };
+
+        let w100 = mock_window(100, true, false, 1, None);
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![w100.clone()]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_new_window(&mut ctx, &w100).expect("Process new window failed");
+
+        assert!(!ctx.state.is_tracked(100));
+        assert!(ctx.state.pending_summons.is_empty());
+    }
+
     #[test]
     fn test_process_new_window_ignores_when_autoadd_false() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let state = AppState::default();
 
@@ -341,8 +927,11 @@ mod tests {
 
         let config = Config {
             window_rule: vec![WindowRule {
-                app_id: Some(Regex::new(r"test").unwrap()),
-                auto_add: false,
+                matches: WindowMatch {
+                    app_id: Some(Regex::new(r"test").unwrap()),
+                    ..Default::default()
+                },
+                auto_add: Some(false),
                 ..Default::default()
             }],
             ..Default::default()
@@ -359,8 +948,8 @@ mod tests {
         process_new_window(&mut ctx, &w100).expect("Process new window failed");
 
         // 100 ignored
-        assert!(!ctx.state.windows.iter().any(|w| w.id == 100));
-        assert_eq!(ctx.state.windows.len(), 0);
+        assert!(!ctx.state.windows(1).iter().any(|w| w.id == 100));
+        assert_eq!(ctx.state.windows(1).len(), 0);
         // Reorder should not have run
         assert!(ctx.socket.sent_actions.is_empty());
     }
@@ -368,9 +957,6 @@ mod tests {
     #[test]
     fn test_process_new_window_ignores_when_no_rule() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let state = AppState::default();
 
@@ -388,8 +974,8 @@ mod tests {
         process_new_window(&mut ctx, &w100).expect("Process new window failed");
 
         // 100 ignored
-        assert!(!ctx.state.windows.iter().any(|w| w.id == 100));
-        assert_eq!(ctx.state.windows.len(), 0);
+        assert!(!ctx.state.windows(1).iter().any(|w| w.id == 100));
+        assert_eq!(ctx.state.windows(1).len(), 0);
         // Reorder should not have run
         assert!(ctx.socket.sent_actions.is_empty());
     }
@@ -397,9 +983,6 @@ mod tests {
     #[test]
     fn test_process_new_window_ignores_after_removed_from_sidebar() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let mut state = AppState::default();
         state.ignored_windows.push(100);
@@ -409,8 +992,11 @@ mod tests {
 
         let config = Config {
             window_rule: vec![WindowRule {
-                app_id: Some(Regex::new(r"test").unwrap()),
-                auto_add: true,
+                matches: WindowMatch {
+                    app_id: Some(Regex::new(r"test").unwrap()),
+                    ..Default::default()
+                },
+                auto_add: Some(true),
                 ..Default::default()
             }],
             ..Default::default()
@@ -427,9 +1013,422 @@ mod tests {
         process_new_window(&mut ctx, &w100).expect("Process new window failed");
 
         // 100 ignored
-        assert!(!ctx.state.windows.iter().any(|w| w.id == 100));
-        assert_eq!(ctx.state.windows.len(), 0);
+        assert!(!ctx.state.windows(1).iter().any(|w| w.id == 100));
+        assert_eq!(ctx.state.windows(1).len(), 0);
         // Reorder should not have run
         assert!(ctx.socket.sent_actions.is_empty());
     }
+
+    #[test]
+    fn test_process_new_window_readopts_stale_window_by_identity() {
+        let temp_dir = tempdir().unwrap();
+
+        // Left behind by a previous niri session, workspace 7 no longer exists
+        let stale = |id: u64, title: &str| WindowState {
+            id,
+            width: 640,
+            height: 480,
+            is_floating: false,
+            position: None,
+            identity: WindowIdentity {
+                app_id: Some("kitty".into()),
+                title: Some(title.into()),
+                pid: Some(42),
+            },
+        };
+        let mut state = AppState {
+            restore_started: Some(Instant::now()),
+            ..Default::default()
+        };
+        state.sidebar_mut(7).windows.push(stale(10, "Terminal"));
+        state.sidebar_mut(7).windows.push(stale(11, "Other"));
+        state.sidebar_mut(7).is_hidden = true;
+
+        let mut w50 = mock_window(50, true, false, 1, None);
+        w50.app_id = Some("kitty".into());
+        w50.title = Some("Other".into());
+        let mock = MockNiri::new(vec![w50.clone()]);
+
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_new_window(&mut ctx, &w50).expect("Process new window failed");
+
+        // The sidebar followed to the new workspace and 50 took the slot of 11
+        assert!(ctx.state.sidebar(7).is_none());
+        let sidebar = ctx.state.sidebar(1).unwrap();
+        assert!(sidebar.is_hidden);
+        let ids: Vec<u64> = sidebar.windows.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![10, 50]);
+        // The size to restore is kept from the old session
+        assert_eq!(
+            (sidebar.windows[1].width, sidebar.windows[1].height),
+            (640, 480)
+        );
+        assert_eq!(sidebar.windows[1].identity.pid, Some(123));
+
+        assert!(
+            ctx.socket
+                .sent_actions
+                .iter()
+                .any(|a| matches!(a, Action::ToggleWindowFloating { id: Some(50) }))
+        );
+    }
+
+    #[test]
+    fn test_process_windows_changed_drops_windows_that_are_gone() {
+        let temp_dir = tempdir().unwrap();
+
+        let tracked = |id: u64, title: &str| WindowState {
+            width: 640,
+            height: 480,
+            identity: WindowIdentity {
+                app_id: Some("test".into()),
+                title: Some(title.into()),
+                pid: None,
+            },
+            ..mock_window_state(id)
+        };
+        let mut state = AppState {
+            restore_started: Some(Instant::now()),
+            ..Default::default()
+        };
+        state
+            .sidebar_mut(1)
+            .windows
+            .push(tracked(10, "Test Window"));
+        state.sidebar_mut(1).windows.push(tracked(11, "Closed"));
+
+        // Same app id, but neither title nor pid match the second window
+        let w50 = mock_window(50, true, false, 1, None);
+        let mut w60 = mock_window(60, false, false, 1, None);
+        w60.title = Some("Unrelated".into());
+        w60.pid = Some(7);
+        let windows = vec![w50, w60];
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(windows.clone()),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_windows_changed(&mut ctx, &windows).expect("Process windows failed");
+
+        // 11 may still open later, it keeps its slot for a while
+        let ids = |ctx: &Ctx<MockNiri>| -> Vec<u64> {
+            ctx.state.windows(1).iter().map(|w| w.id).collect()
+        };
+        assert_eq!(ids(&ctx), vec![50, 11]);
+
+        ctx.state.restore_started = Instant::now().checked_sub(RESTORE_TIMEOUT * 2);
+        process_windows_changed(&mut ctx, &windows).expect("Process windows failed");
+        assert_eq!(ids(&ctx), vec![50]);
+    }
+
+    #[test]
+    fn test_window_opened_after_the_initial_list_is_readopted() {
+        let temp_dir = tempdir().unwrap();
+
+        // Saved before a re-login, none of the windows are open yet
+        let mut state = AppState {
+            restore_started: Some(Instant::now()),
+            ..Default::default()
+        };
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 640,
+            height: 480,
+            identity: WindowIdentity {
+                app_id: Some("kitty".into()),
+                title: Some("Terminal".into()),
+                pid: Some(42),
+            },
+            ..mock_window_state(10)
+        });
+        state.sidebar_mut(1).windows.push(WindowState {
+            identity: WindowIdentity {
+                app_id: Some("test".into()),
+                ..Default::default()
+            },
+            ..mock_window_state(11)
+        });
+
+        let tiled = mock_window(50, true, false, 1, None);
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![tiled.clone()]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        // niri lists the open windows before the sidebar apps are started
+        process_windows_changed(&mut ctx, &[tiled]).expect("Process windows failed");
+        assert_eq!(ctx.state.windows(1).len(), 2);
+
+        let mut kitty = mock_window(70, false, false, 1, None);
+        kitty.app_id = Some("kitty".into());
+        kitty.title = Some("Terminal".into());
+        ctx.socket.windows.push(kitty.clone());
+        process_new_window(&mut ctx, &kitty).expect("Process new window failed");
+
+        let sidebar = ctx.state.sidebar(1).unwrap();
+        let ids: Vec<u64> = sidebar.windows.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![70, 11]);
+        // The size to restore is kept from the old session
+        assert_eq!(
+            (sidebar.windows[0].width, sidebar.windows[0].height),
+            (640, 480)
+        );
+        assert!(
+            ctx.socket
+                .sent_actions
+                .iter()
+                .any(|a| matches!(a, Action::ToggleWindowFloating { id: Some(70) }))
+        );
+    }
+
+    #[test]
+    fn test_process_new_window_ejects_window_once_title_stops_matching() {
+        let temp_dir = tempdir().unwrap();
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(100));
+
+        // Only the picture-in-picture window belongs in the sidebar
+        let config = Config {
+            window_rule: vec![WindowRule {
+                matches: WindowMatch {
+                    app_id: Some(Regex::new("^test$").unwrap()),
+                    ..Default::default()
+                },
+                exclude: vec![WindowMatch {
+                    title: Some(Regex::new("^Picture-in-Picture$").unwrap()),
+                    ..Default::default()
+                }],
+                auto_remove: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut w100 = mock_window(100, true, true, 1, Some((1.0, 2.0)));
+        w100.title = Some("Picture-in-Picture".into());
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: MockNiri::new(vec![w100.clone()]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_new_window(&mut ctx, &w100).expect("Process new window failed");
+        assert!(ctx.state.is_tracked(100));
+
+        w100.title = Some("Some video".into());
+        ctx.socket.windows = vec![w100.clone()];
+        process_new_window(&mut ctx, &w100).expect("Process new window failed");
+
+        assert!(!ctx.state.is_tracked(100));
+        // Restored like a manual removal
+        assert_eq!(ctx.state.ignored_windows, vec![100]);
+        assert!(ctx.socket.sent_actions.iter().any(|a| matches!(
+            a,
+            Action::SetWindowWidth {
+                id: Some(100),
+                change: niri_ipc::SizeChange::SetFixed(1000),
+            }
+        )));
+    }
+
+    #[test]
+    fn test_process_new_window_ejects_tiled_window_for_good() {
+        let temp_dir = tempdir().unwrap();
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(100));
+
+        let config = Config {
+            window_rule: vec![WindowRule {
+                auto_add: Some(true),
+                remove_when_tiled: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        // The event is stale, niri already floats the window
+        let stale = mock_window(100, true, false, 1, None);
+        let floating = mock_window(100, true, true, 1, Some((1.0, 2.0)));
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: MockNiri::new(vec![floating]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        process_new_window(&mut ctx, &stale).expect("Process new window failed");
+        assert!(ctx.state.is_tracked(100));
+
+        // The user moved the window into the tiling layout
+        let tiled = mock_window(100, true, false, 1, None);
+        ctx.socket.windows = vec![tiled.clone()];
+        process_new_window(&mut ctx, &tiled).expect("Process new window failed");
+
+        assert!(!ctx.state.is_tracked(100));
+        assert_eq!(ctx.state.ejected_windows, vec![100]);
+
+        // auto_add does not pull it back in until it closes
+        process_new_window(&mut ctx, &tiled).expect("Process new window failed");
+        assert!(!ctx.state.is_tracked(100));
+
+        process_close(&mut ctx, 100).expect("Process close failed");
+        assert!(ctx.state.ejected_windows.is_empty());
+    }
+
+    #[test]
+    fn test_reload_config_applies_valid_changes_only() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(100));
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![
+                mock_window(100, true, true, 1, Some((1.0, 2.0))),
+                mock_window(200, false, true, 1, None),
+            ]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let valid = config::DEFAULT_CONFIG_STR.replace("width = 400", "width = 555");
+        fs::write(&path, &valid).unwrap();
+        reload_config(&mut ctx, &path, false).expect("Reload failed");
+
+        assert_eq!(ctx.config.geometry.width, 555.into());
+        // Only the sidebar window is resized, and moved into place afterwards
+        let actions = &ctx.socket.sent_actions;
+        assert!(matches!(
+            actions[0],
+            Action::SetWindowWidth {
+                id: Some(100),
+                change: niri_ipc::SizeChange::SetFixed(555),
+            }
+        ));
+        assert!(
+            !actions
+                .iter()
+                .any(|a| matches!(a, Action::SetWindowWidth { id: Some(200), .. }))
+        );
+        assert!(matches!(
+            actions.last(),
+            Some(Action::MoveFloatingWindow { id: Some(100), .. })
+        ));
+
+        ctx.socket.sent_actions.clear();
+        fs::write(&path, "[geometry\n").unwrap();
+        assert!(reload_config(&mut ctx, &path, false).is_err());
+        let negative = config::DEFAULT_CONFIG_STR.replace("gap = 10", "gap = -10");
+        fs::write(&path, negative).unwrap();
+        assert!(reload_config(&mut ctx, &path, true).is_err());
+
+        assert_eq!(ctx.config.geometry.width, 555.into());
+        assert_eq!(ctx.config.geometry.gap, 10.into());
+        assert!(ctx.socket.sent_actions.is_empty());
+
+        fs::remove_file(&path).unwrap();
+        reload_config(&mut ctx, &path, false).expect("Reload failed");
+        assert_eq!(ctx.config.geometry.width, 400.into());
+    }
+
+    #[test]
+    fn test_config_watcher_notices_changes() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        let mut watcher = ConfigWatcher::new(path.clone());
+        assert!(!watcher.changed());
+
+        fs::write(&path, "").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn test_bind_control_socket_replaces_stale_socket() {
+        let temp_dir = tempdir().unwrap();
+        let path = control::socket_path(temp_dir.path());
+
+        // A socket file without a listener behind it, as left by a crashed daemon
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = bind_control_socket(&path).expect("Stale socket should be replaced");
+
+        // A second daemon must not steal the socket of a running one
+        assert!(bind_control_socket(&path).is_err());
+        drop(listener);
+    }
+
+    #[test]
+    fn test_watchers_only_receive_changed_lines() {
+        use std::io::{BufRead, BufReader};
+
+        let temp_dir = tempdir().unwrap();
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 500,
+            height: 500,
+            ..mock_window_state(100)
+        });
+
+        let w100 = mock_window(100, false, true, 1, Some((1.0, 2.0)));
+        let mut ctx = Ctx {
+            state,
+            config: Config::default(),
+            socket: MockNiri::new(vec![w100]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let (daemon_end, client_end) = UnixStream::pair().unwrap();
+        let mut watchers = Watchers::default();
+        watchers.add(daemon_end);
+
+        // New watcher gets the current line, an unchanged sidebar sends nothing
+        watchers.notify(&mut ctx);
+        watchers.notify(&mut ctx);
+        ctx.state.sidebar_mut(1).is_hidden = true;
+        watchers.notify(&mut ctx);
+        drop(watchers);
+
+        let lines: Vec<serde_json::Value> = BufReader::new(client_end)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["alt"], "visible");
+        assert_eq!(lines[1]["alt"], "hidden");
+        assert_eq!(lines[1]["count"], 1);
+    }
+    #[test]
+    fn test_silent_client_times_out() {
+        let (daemon_end, client_end) = UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        // The client never sends a newline, the daemon moves on instead of waiting forever
+        (&client_end).write_all(b"{\"Request\":").unwrap();
+        assert!(handle_client(&daemon_end, &tx).is_err());
+        assert!(rx.try_recv().is_err());
+    }
 }
@@ -4,14 +4,27 @@ mod focus;
 mod hide;
 mod listen;
 mod movefrom;
+mod movewindow;
+mod profile;
 mod reorder;
+mod rules;
+mod status;
+mod summon;
+mod swap;
 mod togglewindow;
 
 pub use close::close;
 pub use flip::toggle_flip;
-pub use focus::focus;
+pub use focus::{FocusTarget, focus, focus_toggle};
 pub use hide::toggle_visibility;
 pub use listen::listen;
 pub use movefrom::move_from;
+pub use movewindow::{Move, move_window};
+pub use profile::{list_profiles, set_profile};
 pub use reorder::reorder;
+pub(crate) use reorder::reorder_refitted;
+pub use rules::{RulesReport, explain_rules, rules_test};
+pub use status::{Status, WaybarStatus, collect_status, status, waybar_status};
+pub use summon::summon;
+pub use swap::swap;
 pub use togglewindow::toggle_window;
@@ -1,3 +1,4 @@
+use crate::state::save_state;
 use crate::{Ctx, NiriClient};
 use anyhow::Result;
 use niri_ipc::{Action, Window, WorkspaceReferenceArg};
@@ -6,15 +7,17 @@ pub fn move_from<C: NiriClient>(ctx: &mut Ctx<C>, workspace: u64) -> Result<()>
     let active_workspace = ctx.socket.get_active_workspace()?.id;
     let windows = ctx.socket.get_windows()?;
 
+    let sidebar = ctx.state.windows(workspace);
     let windows_on_ws: Vec<_> = windows
         .iter()
-        .filter(|w| {
-            w.workspace_id == Some(workspace) && ctx.state.windows.iter().any(|ws| ws.id == w.id)
-        })
+        .filter(|w| w.workspace_id == Some(workspace) && sidebar.iter().any(|ws| ws.id == w.id))
         .collect();
 
     move_to(ctx, windows_on_ws, active_workspace)?;
 
+    ctx.state.move_sidebar(workspace, active_workspace);
+    save_state(&ctx.state, &ctx.cache_dir)?;
+
     Ok(())
 }
 
@@ -42,9 +45,6 @@ mod tests {
     #[test]
     fn test_move_from_only_moves_tracked_windows_on_source_workspace() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         // We track ID 100 and ID 300. We do NOT track ID 200.
         let mut state = AppState::default();
@@ -54,6 +54,7 @@ mod tests {
             height: 500,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 500,
@@ -61,13 +62,15 @@ mod tests {
             height: 500,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-
-        // 2. Setup Mock Windows
+        // 1. Both tracked windows belong to the sidebar of the source workspace
         let source_ws = 2;
         let target_ws = 1;
+        state.sidebar_mut(source_ws).windows.push(w1);
+        state.sidebar_mut(source_ws).windows.push(w2);
+
+        // 2. Setup Mock Windows
 
         // Window 100: On Source WS (1) + Tracked in State -> SHOULD MOVE
         let w100 = mock_window(100, true, false, source_ws, Some((1.0, 2.0)));
@@ -113,5 +116,10 @@ mod tests {
         } else {
             panic!("Unexpected action type sent to socket");
         }
+
+        // The sidebar itself follows the windows to the active workspace
+        assert!(ctx.state.sidebar(source_ws).is_none());
+        let ids: Vec<u64> = ctx.state.windows(target_ws).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![100, 500]);
     }
 }
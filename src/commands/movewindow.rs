@@ -0,0 +1,184 @@
+use crate::Ctx;
+use crate::commands::reorder;
+use crate::config::Insert;
+use crate::niri::NiriClient;
+use crate::state::save_state;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+/// Where to move a window within its sidebar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    /// One place towards the top of the stack
+    Up,
+    /// One place towards the bottom of the stack
+    Down,
+    To(Insert),
+}
+
+/// Moves the focused window to another place in its sidebar
+pub fn move_window<C: NiriClient>(ctx: &mut Ctx<C>, to: Move) -> Result<()> {
+    let focused = ctx.socket.get_active_window()?;
+    let (workspace, index) = ctx
+        .state
+        .find_window(focused.id)
+        .context("Focused window is not in the sidebar")?;
+
+    // Skip windows waiting to be re-adopted after a niri restart, they are not shown
+    let live_ids: HashSet<u64> = ctx.socket.get_windows()?.iter().map(|w| w.id).collect();
+    let sidebar = ctx.state.sidebar_mut(workspace);
+    let moved = match to {
+        Move::Up | Move::Down => {
+            // Flipped sidebars show the end of the list at the bottom
+            let towards_end = (to == Move::Up) != sidebar.is_flipped;
+            let is_live = |i: &usize| live_ids.contains(&sidebar.windows[*i].id);
+            let neighbour = if towards_end {
+                (index + 1..sidebar.windows.len()).find(is_live)
+            } else {
+                (0..index).rev().find(is_live)
+            };
+            if let Some(neighbour) = neighbour {
+                sidebar.windows.swap(index, neighbour);
+            }
+            neighbour.is_some()
+        }
+        Move::To(insert) => {
+            let window = sidebar.windows.remove(index);
+            let target = sidebar.insert_index(insert);
+            sidebar.windows.insert(target, window);
+            target != index
+        }
+    };
+
+    if moved {
+        save_state(&ctx.state, &ctx.cache_dir)?;
+        reorder(ctx)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{AppState, WindowState};
+    use crate::test_utils::{MockNiri, mock_config, mock_window, mock_window_state};
+    use niri_ipc::Action;
+    use std::time::Instant;
+    use tempfile::tempdir;
+
+    fn order(ctx: &Ctx<MockNiri>) -> Vec<u64> {
+        ctx.state.windows(1).iter().map(|w| w.id).collect()
+    }
+
+    #[test]
+    fn test_move_window() {
+        let temp_dir = tempdir().unwrap();
+        let mut state = AppState::default();
+        for id in [1, 2, 3, 4] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 300,
+                height: 500,
+                is_floating: true,
+                ..mock_window_state(id)
+            });
+        }
+        let windows = [1, 2, 3, 4]
+            .map(|id| mock_window(id, id == 2, true, 1, Some((1.0, 2.0))))
+            .to_vec();
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: MockNiri::new(windows),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        move_window(&mut ctx, Move::Up).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 2, 4]);
+        assert!(
+            ctx.socket
+                .sent_actions
+                .iter()
+                .any(|a| matches!(a, Action::MoveFloatingWindow { id: Some(2), .. }))
+        );
+
+        move_window(&mut ctx, Move::To(Insert::Top)).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 4, 2]);
+
+        // Already at the top, nothing to do
+        ctx.socket.sent_actions.clear();
+        move_window(&mut ctx, Move::Up).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 4, 2]);
+        assert!(ctx.socket.sent_actions.is_empty());
+
+        move_window(&mut ctx, Move::Down).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 2, 4]);
+
+        move_window(&mut ctx, Move::To(Insert::Bottom)).expect("Move failed");
+        assert_eq!(order(&ctx), vec![2, 1, 3, 4]);
+
+        move_window(&mut ctx, Move::To(Insert::Index(3))).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 2, 4]);
+
+        move_window(&mut ctx, Move::To(Insert::Index(10))).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 3, 4, 2]);
+    }
+
+    #[test]
+    fn test_move_window_not_in_sidebar() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = Ctx {
+            state: AppState::default(),
+            config: mock_config(),
+            socket: MockNiri::new(vec![mock_window(1, true, false, 1, None)]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let err = move_window(&mut ctx, Move::Up).unwrap_err();
+        assert_eq!(err.to_string(), "Focused window is not in the sidebar");
+    }
+
+    #[test]
+    fn test_move_window_on_flipped_sidebar() {
+        let temp_dir = tempdir().unwrap();
+        let mut state = AppState::default();
+        for id in [1, 2, 3, 4] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 300,
+                height: 500,
+                is_floating: true,
+                ..mock_window_state(id)
+            });
+        }
+        state.sidebar_mut(1).is_flipped = true;
+        // Window 3 is not open, it is waiting to be re-adopted
+        state.sidebar_mut(1).windows[2].identity.app_id = Some("test".into());
+        state.restore_started = Some(Instant::now());
+        let windows = [1, 2, 4]
+            .map(|id| mock_window(id, id == 2, true, 1, Some((1.0, 2.0))))
+            .to_vec();
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: MockNiri::new(windows),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        // Shown from the bottom as 4, 3, 2, 1, so up is towards the start of the list
+        move_window(&mut ctx, Move::Up).expect("Move failed");
+        assert_eq!(order(&ctx), vec![2, 1, 3, 4]);
+
+        // Down skips the window that is not shown
+        move_window(&mut ctx, Move::Down).expect("Move failed");
+        move_window(&mut ctx, Move::Down).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 4, 3, 2]);
+
+        move_window(&mut ctx, Move::To(Insert::Top)).expect("Move failed");
+        assert_eq!(order(&ctx), vec![2, 1, 4, 3]);
+
+        move_window(&mut ctx, Move::To(Insert::Bottom)).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 4, 3, 2]);
+
+        move_window(&mut ctx, Move::To(Insert::Index(2))).expect("Move failed");
+        assert_eq!(order(&ctx), vec![1, 4, 2, 3]);
+    }
+}
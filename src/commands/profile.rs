@@ -0,0 +1,171 @@
+use crate::Ctx;
+use crate::commands::reorder_refitted;
+use crate::commands::togglewindow::refit_sidebar_windows;
+use crate::niri::NiriClient;
+use crate::state::save_state;
+use anyhow::{Result, bail};
+use std::fmt::Write;
+
+/// Switches to the config profile `name`, or back to the plain config if it is `None`
+pub fn set_profile<C: NiriClient>(ctx: &mut Ctx<C>, name: Option<String>) -> Result<()> {
+    let hidden = match &name {
+        Some(name) => match ctx.config.profile.get(name) {
+            Some(profile) => profile.hidden,
+            None => bail!(
+                "No profile named \"{}\" in the config, available: {}",
+                name,
+                profile_names(ctx)
+            ),
+        },
+        None => None,
+    };
+
+    ctx.state.profile = name;
+    if let Some(hidden) = hidden {
+        for sidebar in ctx.state.sidebars.values_mut() {
+            sidebar.is_hidden = hidden;
+        }
+    }
+
+    save_state(&ctx.state, &ctx.cache_dir)?;
+    refit_sidebar_windows(ctx)?;
+    reorder_refitted(ctx)?;
+    Ok(())
+}
+
+/// Lists the profiles in the config, marking the active one
+pub fn list_profiles<C: NiriClient>(ctx: &Ctx<C>) -> String {
+    let active = ctx.state.profile.as_deref();
+    let mut out = String::new();
+    if ctx.config.profile.is_empty() {
+        out.push_str("No profiles configured\n");
+    }
+    for name in ctx.config.profile.keys() {
+        let mark = if Some(name.as_str()) == active {
+            '*'
+        } else {
+            ' '
+        };
+        let _ = writeln!(out, "{} {}", mark, name);
+    }
+    if let Some(active) = active
+        && !ctx.config.profile.contains_key(active)
+    {
+        let _ = writeln!(out, "Active profile \"{}\" is not in the config", active);
+    }
+    out.trim_end().to_string()
+}
+
+fn profile_names<C: NiriClient>(ctx: &Ctx<C>) -> String {
+    if ctx.config.profile.is_empty() {
+        return "none".to_string();
+    }
+    ctx.config
+        .profile
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GeometryOverride, InteractionOverride, ProfileConfig, SidebarPosition};
+    use crate::state::AppState;
+    use crate::test_utils::{MockNiri, mock_config, mock_ctx, mock_window, mock_window_state};
+    use niri_ipc::{Action, SizeChange};
+    use tempfile::{TempDir, tempdir};
+
+    fn ctx(temp_dir: &TempDir) -> Ctx<MockNiri> {
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(100));
+
+        let mut config = mock_config();
+        config.profile.insert(
+            "coding".into(),
+            ProfileConfig {
+                geometry: GeometryOverride {
+                    width: Some(600.into()),
+                    ..Default::default()
+                },
+                interaction: InteractionOverride {
+                    position: Some(SidebarPosition::Left),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        config.profile.insert(
+            "presentation".into(),
+            ProfileConfig {
+                hidden: Some(true),
+                ..Default::default()
+            },
+        );
+
+        let windows = vec![mock_window(100, true, true, 1, Some((1.0, 2.0)))];
+        Ctx {
+            config,
+            ..mock_ctx(temp_dir.path(), state, windows)
+        }
+    }
+
+    #[test]
+    fn test_set_profile_applies_overrides() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = ctx(&temp_dir);
+
+        set_profile(&mut ctx, Some("coding".into())).expect("Switching profile failed");
+
+        assert_eq!(ctx.state.profile.as_deref(), Some("coding"));
+        let layout = ctx.layout_for(Some("eDP-1"));
+        assert_eq!(layout.interaction.position, SidebarPosition::Left);
+        assert_eq!(layout.geometry.height, 200.into());
+        assert!(ctx.socket.sent_actions.iter().any(|a| matches!(
+            a,
+            Action::SetWindowWidth {
+                id: Some(100),
+                change: SizeChange::SetFixed(600),
+            }
+        )));
+
+        set_profile(&mut ctx, None).expect("Switching profile failed");
+        assert_eq!(ctx.state.profile, None);
+        assert_eq!(
+            ctx.layout_for(Some("eDP-1")).interaction.position,
+            SidebarPosition::Right
+        );
+    }
+
+    #[test]
+    fn test_set_profile_can_hide_sidebars() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = ctx(&temp_dir);
+
+        set_profile(&mut ctx, Some("presentation".into())).expect("Switching profile failed");
+        assert!(ctx.state.sidebar(1).unwrap().is_hidden);
+
+        // Profiles without `hidden` leave the sidebars alone
+        set_profile(&mut ctx, Some("coding".into())).expect("Switching profile failed");
+        assert!(ctx.state.sidebar(1).unwrap().is_hidden);
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = ctx(&temp_dir);
+
+        let err = set_profile(&mut ctx, Some("docked".into())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No profile named \"docked\" in the config, available: coding, presentation"
+        );
+        assert_eq!(ctx.state.profile, None);
+
+        ctx.state.profile = Some("coding".into());
+        assert_eq!(list_profiles(&ctx), "* coding\n  presentation");
+        ctx.state.profile = Some("docked".into());
+        assert!(list_profiles(&ctx).ends_with("Active profile \"docked\" is not in the config"));
+    }
+}
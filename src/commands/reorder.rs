@@ -1,50 +1,193 @@
-use crate::config::SidebarPosition;
+use crate::config::{
+    Align, Geometry, OutputLayout, PixelMargins, SidebarPosition, Size, StackLayout,
+};
 use crate::niri::NiriClient;
 use crate::state::save_state;
-use crate::window_rules::{resolve_rule_focus_peek, resolve_rule_peek, resolve_window_size};
+use crate::window_rules::{EffectiveRule, RuleTarget, resolve_rule};
 use crate::{Ctx, WindowTarget};
 use anyhow::Result;
-use niri_ipc::{Action, PositionChange, Window};
+use niri_ipc::{Action, PositionChange, SizeChange, Window, Workspace};
 use std::collections::HashSet;
+use std::slice;
 
-fn resolve_dimensions<C: NiriClient>(window: &Window, ctx: &Ctx<C>) -> WindowTarget {
-    let (width, height) = resolve_window_size(
-        &ctx.config.window_rule,
-        window,
-        ctx.config.geometry.width,
-        ctx.config.geometry.height,
-    );
+fn resolve_dimensions(
+    rule: &EffectiveRule,
+    layout: &OutputLayout,
+    screen: (i32, i32),
+) -> WindowTarget {
+    let (width, height) = rule.size(layout.geometry.width, layout.geometry.height, screen);
 
     WindowTarget { width, height }
 }
 
-fn calculate_coordinates<C: NiriClient>(
+/// Where a window goes within the sidebar, relative to its first window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    /// Distance along the screen edge
+    stack_offset: i32,
+    /// Distance away from the screen edge, non-zero for every line but the first
+    lane_offset: i32,
+}
+
+/// Splits the windows into lines according to the geometry's layout, aligns every
+/// line along the screen edge and returns the slot of each window
+fn calculate_slots(
+    pos: SidebarPosition,
+    geometry: &Geometry,
+    all_dims: &[WindowTarget],
+    screen: (i32, i32),
+    margins: &PixelMargins,
+) -> Vec<Slot> {
+    let (sw, sh) = screen;
+    let gap = match pos {
+        SidebarPosition::Left | SidebarPosition::Right => geometry.gap.resolve(sh),
+        SidebarPosition::Top | SidebarPosition::Bottom => geometry.gap.resolve(sw),
+    };
+    // Size along the edge and size away from the edge
+    let extent = |dims: &WindowTarget| match pos {
+        SidebarPosition::Left | SidebarPosition::Right => (dims.height, dims.width),
+        SidebarPosition::Top | SidebarPosition::Bottom => (dims.width, dims.height),
+    };
+    let available = match pos {
+        SidebarPosition::Left | SidebarPosition::Right => sh - margins.top - margins.bottom,
+        SidebarPosition::Top | SidebarPosition::Bottom => sw - margins.left - margins.right,
+    };
+
+    // Line and offset along the edge of every window, plus the thickness of every line
+    let mut placed = Vec::with_capacity(all_dims.len());
+    let mut line_lengths: Vec<i32> = vec![];
+    let mut line_thickness: Vec<i32> = vec![];
+    for (index, dims) in all_dims.iter().enumerate() {
+        let (length, thickness) = extent(dims);
+        let line = match geometry.layout {
+            StackLayout::Stack => 0,
+            StackLayout::Grid => index % geometry.columns.max(1),
+            StackLayout::Wrap => {
+                let current = line_lengths.len().saturating_sub(1);
+                match line_lengths.get(current) {
+                    Some(&used) if used > 0 && used + length > available => current + 1,
+                    _ => current,
+                }
+            }
+        };
+        if line >= line_lengths.len() {
+            line_lengths.resize(line + 1, 0);
+            line_thickness.resize(line + 1, 0);
+        }
+
+        placed.push((line, line_lengths[line]));
+        line_lengths[line] += length + gap;
+        line_thickness[line] = line_thickness[line].max(thickness);
+    }
+
+    // Stacks grow upwards from the bottom and rightwards from the left,
+    // so aligning moves a line away from where it grows from
+    let align = geometry.align.unwrap_or(match pos {
+        SidebarPosition::Left | SidebarPosition::Right => Align::End,
+        SidebarPosition::Top | SidebarPosition::Bottom => Align::Start,
+    });
+    let grows_from = match pos {
+        SidebarPosition::Left | SidebarPosition::Right => Align::End,
+        SidebarPosition::Top | SidebarPosition::Bottom => Align::Start,
+    };
+    let align_shift = |line: usize| {
+        // The gap after the last window is not part of the line
+        let free = available - (line_lengths[line] - gap);
+        match align {
+            Align::Center => free / 2,
+            _ if align == grows_from => 0,
+            _ => free,
+        }
+    };
+
+    placed
+        .into_iter()
+        .map(|(line, stack_offset)| Slot {
+            stack_offset: stack_offset + align_shift(line),
+            lane_offset: line_thickness[..line].iter().map(|t| t + gap).sum(),
+        })
+        .collect()
+}
+
+/// Shrinks `dims` to `size` along the screen edge
+fn collapse(
     pos: SidebarPosition,
     dims: WindowTarget,
+    size: Size,
     screen: (i32, i32),
-    stack_offset: i32,
+) -> WindowTarget {
+    match pos {
+        SidebarPosition::Left | SidebarPosition::Right => WindowTarget {
+            height: size.resolve(screen.1),
+            ..dims
+        },
+        SidebarPosition::Top | SidebarPosition::Bottom => WindowTarget {
+            width: size.resolve(screen.0),
+            ..dims
+        },
+    }
+}
+
+/// Expands or collapses an accordion window, only if it is not at its size already
+/// unless `force` is set
+fn resize_along_edge<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window: &Window,
+    pos: SidebarPosition,
+    dims: WindowTarget,
+    force: bool,
+) {
+    let (width, height) = window.layout.window_size;
+    let action = match pos {
+        SidebarPosition::Left | SidebarPosition::Right if force || height != dims.height => {
+            Action::SetWindowHeight {
+                id: Some(window.id),
+                change: SizeChange::SetFixed(dims.height),
+            }
+        }
+        SidebarPosition::Top | SidebarPosition::Bottom if force || width != dims.width => {
+            Action::SetWindowWidth {
+                id: Some(window.id),
+                change: SizeChange::SetFixed(dims.width),
+            }
+        }
+        _ => return,
+    };
+    let _ = ctx.socket.send_action(action);
+}
+
+fn calculate_coordinates(
+    pos: SidebarPosition,
+    dims: WindowTarget,
+    screen: (i32, i32),
+    slot: Slot,
     active_peek: i32,
-    ctx: &Ctx<C>,
+    is_hidden: bool,
+    margins: &PixelMargins,
 ) -> (i32, i32) {
-    let state = &ctx.state;
-    let margins = &ctx.config.margins;
     let (sw, sh) = screen;
     let (w, h) = (dims.width, dims.height);
+    let Slot {
+        stack_offset,
+        lane_offset,
+    } = slot;
 
+    // Lines further from the edge are pushed further off screen when hidden,
+    // so only the first one peeks out
     match pos {
         SidebarPosition::Right => {
-            let visible_x = sw - w - margins.right;
-            let hidden_x = sw - active_peek;
-            let x = if state.is_hidden { hidden_x } else { visible_x };
+            let visible_x = sw - w - margins.right - lane_offset;
+            let hidden_x = sw - active_peek + lane_offset;
+            let x = if is_hidden { hidden_x } else { visible_x };
 
             let start_y = sh - h - margins.bottom;
             let y = start_y - stack_offset;
             (x, y)
         }
         SidebarPosition::Left => {
-            let visible_x = margins.left;
-            let hidden_x = -w + active_peek;
-            let x = if state.is_hidden { hidden_x } else { visible_x };
+            let visible_x = margins.left + lane_offset;
+            let hidden_x = -w + active_peek - lane_offset;
+            let x = if is_hidden { hidden_x } else { visible_x };
 
             let start_y = sh - h - margins.bottom;
             let y = start_y - stack_offset;
@@ -54,89 +197,166 @@ fn calculate_coordinates<C: NiriClient>(
             let start_x = margins.left;
             let x = start_x + stack_offset;
 
-            let visible_y = sh - h - margins.bottom;
-            let hidden_y = sh - active_peek;
-            let y = if state.is_hidden { hidden_y } else { visible_y };
+            let visible_y = sh - h - margins.bottom - lane_offset;
+            let hidden_y = sh - active_peek + lane_offset;
+            let y = if is_hidden { hidden_y } else { visible_y };
             (x, y)
         }
         SidebarPosition::Top => {
             let start_x = margins.left;
             let x = start_x + stack_offset;
 
-            let visible_y = margins.top;
-            let hidden_y = -h + active_peek;
-            let y = if state.is_hidden { hidden_y } else { visible_y };
+            let visible_y = margins.top + lane_offset;
+            let hidden_y = -h + active_peek - lane_offset;
+            let y = if is_hidden { hidden_y } else { visible_y };
             (x, y)
         }
     }
 }
 
 pub fn reorder<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
-    let (display_w, display_h) = ctx.socket.get_screen_dimensions()?;
-    let current_ws = ctx.socket.get_active_workspace()?.id;
+    reorder_sidebars(ctx, false)
+}
+
+/// Reorders right after the sidebar windows were resized. niri still reports their
+/// old sizes then, so accordion windows are collapsed or expanded whatever size they have.
+pub(crate) fn reorder_refitted<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
+    reorder_sidebars(ctx, true)
+}
+
+fn reorder_sidebars<C: NiriClient>(ctx: &mut Ctx<C>, force_resize: bool) -> Result<()> {
+    let workspaces = ctx.socket.get_workspaces()?;
     let all_windows = ctx.socket.get_windows()?;
 
-    let sidebar_ids: Vec<u64> = ctx.state.windows.iter().map(|w| w.id).collect();
+    let active_ids: HashSet<u64> = all_windows.iter().map(|w| w.id).collect();
+    let mut changed = ctx.state.prune_closed(&active_ids);
+
+    // Every output shows the sidebar of its own active workspace
+    for workspace in workspaces.iter().filter(|w| w.is_active) {
+        let Some(output) = workspace.output.as_deref() else {
+            continue;
+        };
+        let Some(sidebar) = ctx.state.sidebars.get_mut(&workspace.id) else {
+            continue;
+        };
+        if sidebar.output.as_deref() != Some(output) {
+            sidebar.output = Some(output.to_string());
+            changed = true;
+        }
+
+        // A disabled or just unplugged output must not hold up the others
+        let screen = match ctx.socket.get_output_dimensions(output) {
+            Ok(screen) => screen,
+            Err(e) => {
+                eprintln!("niri-sidebar: Skipping output {}: {:#}", output, e);
+                continue;
+            }
+        };
+        reorder_sidebar(ctx, workspace, output, screen, &all_windows, force_resize);
+    }
+
+    if changed {
+        save_state(&ctx.state, &ctx.cache_dir)?;
+    }
+
+    Ok(())
+}
+
+fn reorder_sidebar<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    workspace: &Workspace,
+    output: &str,
+    screen: (i32, i32),
+    all_windows: &[Window],
+    force_resize: bool,
+) {
+    let Some(sidebar) = ctx.state.sidebar(workspace.id) else {
+        return;
+    };
+    let is_hidden = sidebar.is_hidden;
+    let is_flipped = sidebar.is_flipped;
+    let last_focused = sidebar.last_focused();
+    let sidebar_ids: Vec<u64> = sidebar.windows.iter().map(|w| w.id).collect();
+
     let mut sidebar_windows: Vec<_> = all_windows
         .iter()
         .filter(|w| {
-            w.is_floating && w.workspace_id == Some(current_ws) && sidebar_ids.contains(&w.id)
+            w.is_floating && w.workspace_id == Some(workspace.id) && sidebar_ids.contains(&w.id)
         })
         .collect();
 
-    let initial_len = ctx.state.windows.len();
-    let active_ids: HashSet<u64> = all_windows.iter().map(|w| w.id).collect();
-
-    ctx.state.windows.retain(|w| active_ids.contains(&w.id));
-    if ctx.state.windows.len() != initial_len {
-        save_state(&ctx.state, &ctx.cache_dir)?;
-    }
-
-    // Sort by ID for stable ordering
+    // Sort by position in the sidebar for stable ordering
     sidebar_windows.sort_by_key(|w| {
         sidebar_ids
             .iter()
             .position(|id| *id == w.id)
             .unwrap_or(usize::MAX)
     });
-    if ctx.state.is_flipped {
+    if is_flipped {
         sidebar_windows.reverse();
     }
 
-    let position = ctx.config.interaction.position;
-    let gap = ctx.config.geometry.gap;
-
-    let mut current_stack_offset = 0;
+    let layout = ctx.layout_for(Some(output));
+    let position = layout.interaction.position;
 
-    for window in sidebar_windows.iter() {
-        let dims = resolve_dimensions(window, ctx);
+    // In accordion mode only one window keeps its full size
+    let accordion = layout.geometry.accordion;
+    let expanded = sidebar_windows
+        .iter()
+        .find(|w| w.is_focused)
+        .or_else(|| sidebar_windows.iter().find(|w| Some(w.id) == last_focused))
+        .or(sidebar_windows.first())
+        .map(|w| w.id);
 
+    let rules: Vec<EffectiveRule> = sidebar_windows
+        .iter()
+        .map(|w| {
+            let target = RuleTarget::new(w, slice::from_ref(workspace), &ctx.state);
+            resolve_rule(&ctx.config.window_rule, &target)
+        })
+        .collect();
+    let all_dims: Vec<WindowTarget> = sidebar_windows
+        .iter()
+        .zip(&rules)
+        .map(|(w, rule)| {
+            let dims = resolve_dimensions(rule, &layout, screen);
+            if accordion && Some(w.id) != expanded {
+                collapse(position, dims, layout.geometry.collapsed, screen)
+            } else {
+                dims
+            }
+        })
+        .collect();
+    let margins = layout.margins.resolve(screen);
+    let slots = calculate_slots(position, &layout.geometry, &all_dims, screen, &margins);
+
+    // Peeks are measured across the sidebar
+    let peek_total = match position {
+        SidebarPosition::Left | SidebarPosition::Right => screen.0,
+        SidebarPosition::Top | SidebarPosition::Bottom => screen.1,
+    };
+
+    for (((window, rule), dims), slot) in
+        sidebar_windows.iter().zip(&rules).zip(all_dims).zip(slots)
+    {
         let active_peek = if window.is_focused {
-            resolve_rule_focus_peek(
-                &ctx.config.window_rule,
-                window,
-                ctx.config.interaction.get_focus_peek(),
-            )
+            rule.focus_peek(layout.interaction.get_focus_peek(), peek_total)
         } else {
-            resolve_rule_peek(&ctx.config.window_rule, window, ctx.config.interaction.peek)
+            rule.peek(layout.interaction.peek, peek_total)
         };
 
         let (target_x, target_y) = calculate_coordinates(
             position,
             dims,
-            (display_w, display_h),
-            current_stack_offset,
+            screen,
+            slot,
             active_peek,
-            ctx,
+            is_hidden,
+            &margins,
         );
 
-        match position {
-            SidebarPosition::Left | SidebarPosition::Right => {
-                current_stack_offset += dims.height + gap;
-            }
-            SidebarPosition::Top | SidebarPosition::Bottom => {
-                current_stack_offset += dims.width + gap;
-            }
+        if accordion {
+            resize_along_edge(ctx, window, position, dims, force_resize);
         }
 
         let _ = ctx.socket.send_action(Action::MoveFloatingWindow {
@@ -145,18 +365,17 @@ pub fn reorder<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
             y: PositionChange::SetFixed(target_y.into()),
         });
     }
-
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::WindowRule;
-    use crate::state::{AppState, WindowState};
-    use crate::test_utils::{MockNiri, mock_config, mock_window};
+    use crate::config::{WindowMatch, WindowRule};
+    use crate::state::{AppState, RESTORE_TIMEOUT, WindowIdentity, WindowState};
+    use crate::test_utils::{MockNiri, mock_config, mock_window, mock_window_state};
     use niri_ipc::{Action, PositionChange};
     use regex::Regex;
+    use std::time::Instant;
     use tempfile::tempdir;
 
     #[test]
@@ -175,6 +394,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -182,9 +402,10 @@ mod tests {
             height: 200,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let mut ctx = Ctx {
             state,
@@ -231,16 +452,15 @@ mod tests {
         let w_bg = mock_window(2, false, true, 1, Some((1.0, 2.0)));
         let mock = MockNiri::new(vec![w_focused, w_bg]);
 
-        let mut state = AppState {
-            is_hidden: true,
-            ..Default::default()
-        };
+        let mut state = AppState::default();
+        state.sidebar_mut(1).is_hidden = true;
         let w1 = WindowState {
             id: 1,
             width: 300,
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -248,9 +468,10 @@ mod tests {
             height: 200,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let mut ctx = Ctx {
             state,
@@ -277,6 +498,57 @@ mod tests {
         )));
     }
 
+    #[test]
+    fn test_prunes_closed_window_with_identity() {
+        let temp_dir = tempdir().unwrap();
+        let live = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![live.clone()]);
+
+        // Window 2 was closed while no daemon was running
+        let mut state = AppState::default();
+        for id in [1, 2] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 100,
+                height: 100,
+                identity: WindowIdentity::of(&live),
+                ..mock_window_state(id)
+            });
+        }
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).unwrap();
+        let ids: Vec<u64> = ctx.state.windows(1).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![1]);
+
+        // Only kept for a while after the daemon started, the window may still open again
+        let stale = || WindowState {
+            width: 100,
+            height: 100,
+            identity: WindowIdentity::of(&live),
+            ..mock_window_state(2)
+        };
+        ctx.state.sidebar_mut(1).windows.push(stale());
+        ctx.state.restore_started = Some(Instant::now());
+        reorder(&mut ctx).unwrap();
+        assert_eq!(ctx.state.windows(1).len(), 2);
+
+        // Without an identity there is nothing to re-adopt it by
+        ctx.state.sidebar_mut(1).windows[1].identity = Default::default();
+        reorder(&mut ctx).unwrap();
+        assert_eq!(ctx.state.windows(1).len(), 1);
+
+        ctx.state.sidebar_mut(1).windows.push(stale());
+        ctx.state.restore_started = Instant::now().checked_sub(RESTORE_TIMEOUT * 2);
+        reorder(&mut ctx).unwrap();
+        assert_eq!(ctx.state.windows(1).len(), 1);
+    }
+
     #[test]
     fn test_filters_wrong_workspace_and_cleanup_zombies() {
         let temp_dir = tempdir().unwrap();
@@ -296,6 +568,7 @@ mod tests {
             height: 100,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -303,6 +576,7 @@ mod tests {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
         let w3 = WindowState {
             id: 3,
@@ -310,10 +584,11 @@ mod tests {
             height: 100,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-        state.windows.push(w3);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
+        state.sidebar_mut(1).windows.push(w3);
 
         let mut ctx = Ctx {
             state,
@@ -329,7 +604,7 @@ mod tests {
         // 2. Window 2 should NOT be moved
         // 3. Window 1 SHOULD be moved
 
-        let ids: Vec<u64> = ctx.state.windows.iter().map(|w| w.id).collect();
+        let ids: Vec<u64> = ctx.state.windows(1).iter().map(|w| w.id).collect();
         assert!(ids.contains(&1));
         assert!(ids.contains(&2));
         assert!(
@@ -368,16 +643,15 @@ mod tests {
         let w2 = mock_window(2, false, true, 1, Some((1.0, 2.0)));
         let mock = MockNiri::new(vec![w1, w2]);
 
-        let mut state = AppState {
-            is_flipped: true,
-            ..Default::default()
-        };
+        let mut state = AppState::default();
+        state.sidebar_mut(1).is_flipped = true;
         let w1 = WindowState {
             id: 1,
             width: 300,
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -385,9 +659,10 @@ mod tests {
             height: 200,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let mut ctx = Ctx {
             state,
@@ -427,14 +702,12 @@ mod tests {
 
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Left;
-        config.interaction.peek = 10;
-        config.geometry.width = 300;
-        config.margins.left = 0;
+        config.interaction.peek = 10.into();
+        config.geometry.width = 300.into();
+        config.margins.left = 0.into();
 
-        let mut state = AppState {
-            is_hidden: true,
-            ..Default::default()
-        };
+        let mut state = AppState::default();
+        state.sidebar_mut(1).is_hidden = true;
 
         let w1 = WindowState {
             id: 1,
@@ -442,8 +715,9 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mut ctx = Ctx {
             state,
@@ -475,9 +749,9 @@ mod tests {
 
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Bottom;
-        config.geometry.width = 100;
-        config.geometry.gap = 10;
-        config.margins.left = 20;
+        config.geometry.width = 100.into();
+        config.geometry.gap = 10.into();
+        config.margins.left = 20.into();
 
         let mut state = AppState::default();
 
@@ -487,6 +761,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -494,9 +769,10 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
         let mut ctx = Ctx {
             state,
@@ -536,13 +812,16 @@ mod tests {
 
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Right;
-        config.geometry.width = 300;
-        config.interaction.peek = 10;
+        config.geometry.width = 300.into();
+        config.interaction.peek = 10.into();
 
         config.window_rule = vec![WindowRule {
-            app_id: Some(Regex::new("special").unwrap()),
-            width: Some(500),
-            peek: Some(100),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("special").unwrap()),
+                ..Default::default()
+            },
+            width: Some(500.into()),
+            peek: Some(100.into()),
             ..Default::default()
         }];
 
@@ -555,6 +834,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -562,11 +842,12 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
-        state.is_hidden = true;
+        state.sidebar_mut(1).is_hidden = true;
 
         let mut ctx = Ctx {
             state,
@@ -618,14 +899,17 @@ mod tests {
 
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Left;
-        config.interaction.peek = 10;
-        config.geometry.width = 300;
-        config.margins.left = 0;
+        config.interaction.peek = 10.into();
+        config.geometry.width = 300.into();
+        config.margins.left = 0.into();
 
         config.window_rule = vec![WindowRule {
-            app_id: Some(Regex::new("special").unwrap()),
-            width: Some(400),
-            peek: Some(50),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("special").unwrap()),
+                ..Default::default()
+            },
+            width: Some(400.into()),
+            peek: Some(50.into()),
             ..Default::default()
         }];
 
@@ -637,6 +921,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -644,11 +929,12 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
 
-        state.is_hidden = true;
+        state.sidebar_mut(1).is_hidden = true;
 
         let mut ctx = Ctx {
             state,
@@ -696,13 +982,16 @@ mod tests {
 
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Bottom;
-        config.geometry.width = 100;
-        config.geometry.gap = 10;
-        config.margins.left = 0;
+        config.geometry.width = 100.into();
+        config.geometry.gap = 10.into();
+        config.margins.left = 0.into();
 
         config.window_rule = vec![WindowRule {
-            app_id: Some(Regex::new("wide").unwrap()),
-            width: Some(200),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("wide").unwrap()),
+                ..Default::default()
+            },
+            width: Some(200.into()),
             ..Default::default()
         }];
 
@@ -714,6 +1003,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -721,9 +1011,10 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1); // Will be processed first
-        state.windows.push(w2); // Will be processed second
+        state.sidebar_mut(1).windows.push(w1); // Will be processed first
+        state.sidebar_mut(1).windows.push(w2); // Will be processed second
 
         let mut ctx = Ctx {
             state,
@@ -771,14 +1062,17 @@ mod tests {
         let mock = MockNiri::new(vec![w1, w2, w3]);
         let mut config = mock_config();
         config.interaction.position = SidebarPosition::Right;
-        config.geometry.height = 200;
-        config.geometry.gap = 10;
-        config.margins.top = 0;
-        config.margins.right = 0;
-        config.margins.bottom = 0;
+        config.geometry.height = 200.into();
+        config.geometry.gap = 10.into();
+        config.margins.top = 0.into();
+        config.margins.right = 0.into();
+        config.margins.bottom = 0.into();
         config.window_rule = vec![WindowRule {
-            app_id: Some(Regex::new("tall").unwrap()),
-            height: Some(400),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("tall").unwrap()),
+                ..Default::default()
+            },
+            height: Some(400.into()),
             ..Default::default()
         }];
         let mut state = AppState::default();
@@ -788,6 +1082,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 2,
@@ -795,6 +1090,7 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w3 = WindowState {
             id: 3,
@@ -802,10 +1098,11 @@ mod tests {
             height: 200,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
-        state.windows.push(w2);
-        state.windows.push(w3);
+        state.sidebar_mut(1).windows.push(w1);
+        state.sidebar_mut(1).windows.push(w2);
+        state.sidebar_mut(1).windows.push(w3);
         let mut ctx = Ctx {
             state,
             config,
@@ -852,4 +1149,458 @@ mod tests {
             } if *y == 260.0
         )));
     }
+
+    #[test]
+    fn test_only_current_workspace_sidebar_is_laid_out() {
+        let temp_dir = tempdir().unwrap();
+        // Scenario: Workspace 1 (active) and workspace 2 both have a sidebar.
+        // Only the sidebar of workspace 1 is laid out, using its own hidden flag.
+        let w1 = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let w2 = mock_window(2, false, true, 2, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![w1, w2]);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 300,
+            height: 200,
+            ..mock_window_state(1)
+        });
+        state.sidebar_mut(2).windows.push(WindowState {
+            width: 300,
+            height: 200,
+            ..mock_window_state(2)
+        });
+        state.sidebar_mut(2).is_hidden = true;
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+
+        let actions = &ctx.socket.sent_actions;
+        assert_eq!(actions.len(), 1);
+
+        // Visible X = 1920 - 300 - 20 = 1600, the hidden flag of workspace 2 is not used
+        assert!(actions.iter().any(|a| matches!(a,
+            Action::MoveFloatingWindow { id: Some(1), x: PositionChange::SetFixed(x), .. }
+            if *x == 1600.0
+        )));
+    }
+
+    #[test]
+    fn test_each_output_uses_its_own_layout() {
+        let temp_dir = tempdir().unwrap();
+        // Scenario: Workspace 1 on "eDP-1" (1920x1080, global config, right side)
+        // and workspace 2 on "DP-1" (3840x2160, left side, wider windows).
+        let w1 = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let w2 = mock_window(2, false, true, 2, Some((1.0, 2.0)));
+        let mut mock = MockNiri::new(vec![w1, w2]);
+        mock.workspaces
+            .push(crate::test_utils::mock_workspace(2, "DP-1", false));
+        mock.outputs.insert("DP-1".into(), (3840, 2160));
+
+        let mut config = mock_config();
+        config.output.insert(
+            "DP-1".into(),
+            crate::config::OutputConfig {
+                position: Some(SidebarPosition::Left),
+                geometry: crate::config::GeometryOverride {
+                    width: Some(600.into()),
+                    ..Default::default()
+                },
+                margins: crate::config::MarginsOverride {
+                    left: Some(40.into()),
+                    ..Default::default()
+                },
+            },
+        );
+
+        let mut state = AppState::default();
+        for (ws, id) in [(1, 1), (2, 2)] {
+            state.sidebar_mut(ws).windows.push(WindowState {
+                width: 300,
+                height: 200,
+                ..mock_window_state(id)
+            });
+        }
+
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+
+        let actions = &ctx.socket.sent_actions;
+        assert_eq!(actions.len(), 2);
+
+        // eDP-1: X = 1920 - 300 - 20 = 1600, Y = 1080 - 200 - 50 = 830
+        assert!(actions.iter().any(|a| matches!(a,
+            Action::MoveFloatingWindow {
+                id: Some(1),
+                x: PositionChange::SetFixed(x),
+                y: PositionChange::SetFixed(y)
+            } if *x == 1600.0 && *y == 830.0
+        )));
+
+        // DP-1: X = Margin Left = 40, Y = 2160 - 200 - 50 = 1910
+        assert!(actions.iter().any(|a| matches!(a,
+            Action::MoveFloatingWindow {
+                id: Some(2),
+                x: PositionChange::SetFixed(x),
+                y: PositionChange::SetFixed(y)
+            } if *x == 40.0 && *y == 1910.0
+        )));
+
+        // Sidebars remember which output they were laid out on
+        assert_eq!(
+            ctx.state.sidebar(2).unwrap().output.as_deref(),
+            Some("DP-1")
+        );
+    }
+
+    #[test]
+    fn test_output_without_size_is_skipped() {
+        let temp_dir = tempdir().unwrap();
+        // Workspace 2 is on an output niri has no logical size for, like a disabled one
+        let w1 = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let w2 = mock_window(2, false, true, 2, Some((1.0, 2.0)));
+        let mut mock = MockNiri::new(vec![w1, w2]);
+        mock.workspaces
+            .push(crate::test_utils::mock_workspace(2, "DP-1", false));
+
+        let mut state = AppState::default();
+        for (ws, id) in [(1, 1), (2, 2)] {
+            state.sidebar_mut(ws).windows.push(mock_window_state(id));
+        }
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+
+        // The sidebar on eDP-1 is still laid out
+        let moved: Vec<Option<u64>> = ctx
+            .socket
+            .sent_actions
+            .iter()
+            .filter_map(|a| match a {
+                Action::MoveFloatingWindow { id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moved, vec![Some(1)]);
+    }
+
+    #[test]
+    fn test_slots_for_grid_and_wrap() {
+        let margins = mock_config().margins.resolve((1920, 1080));
+        let geometry = |layout, columns| Geometry {
+            layout,
+            columns,
+            ..mock_config().geometry
+        };
+        let dims = vec![
+            WindowTarget {
+                width: 300,
+                height: 200,
+            };
+            5
+        ];
+        let slot = |stack_offset, lane_offset| Slot {
+            stack_offset,
+            lane_offset,
+        };
+
+        // Grid: rows of two, the second line starts one window width + gap away from the edge
+        let grid = calculate_slots(
+            SidebarPosition::Right,
+            &geometry(StackLayout::Grid, 2),
+            &dims[..3],
+            (1920, 1080),
+            &margins,
+        );
+        assert_eq!(grid, vec![slot(0, 0), slot(0, 310), slot(210, 0)]);
+
+        // Wrap: 1080 - 50 - 50 = 980 fits four windows of 200 + 10 gap
+        let wrap = calculate_slots(
+            SidebarPosition::Left,
+            &geometry(StackLayout::Wrap, 2),
+            &dims,
+            (1920, 1080),
+            &margins,
+        );
+        assert_eq!(
+            wrap,
+            vec![
+                slot(0, 0),
+                slot(210, 0),
+                slot(420, 0),
+                slot(630, 0),
+                slot(0, 310)
+            ]
+        );
+
+        // Bottom: lines are stacked upwards, so they are as far apart as the windows are high
+        let bottom = calculate_slots(
+            SidebarPosition::Bottom,
+            &geometry(StackLayout::Grid, 3),
+            &dims[..4],
+            (1920, 1080),
+            &margins,
+        );
+        assert_eq!(
+            bottom,
+            vec![slot(0, 0), slot(0, 210), slot(0, 420), slot(310, 0)]
+        );
+    }
+
+    #[test]
+    fn test_relative_gap_is_measured_along_the_edge() {
+        let margins = mock_config().margins.resolve((1920, 1080));
+        let geometry = Geometry {
+            gap: Size::Fraction(0.01),
+            layout: StackLayout::Grid,
+            columns: 2,
+            ..mock_config().geometry
+        };
+        let dims = [WindowTarget {
+            width: 300,
+            height: 200,
+        }; 3];
+        let slots = |pos| calculate_slots(pos, &geometry, &dims, (1920, 1080), &margins);
+
+        // 1% of 1080 along a right edge, 1% of 1920 along a bottom one
+        let right = slots(SidebarPosition::Right);
+        assert_eq!((right[1].lane_offset, right[2].stack_offset), (311, 211));
+        let bottom = slots(SidebarPosition::Bottom);
+        assert_eq!((bottom[1].lane_offset, bottom[2].stack_offset), (219, 319));
+    }
+
+    #[test]
+    fn test_relative_collapsed_is_measured_along_the_edge() {
+        let dims = WindowTarget {
+            width: 300,
+            height: 200,
+        };
+        let size = Size::Fraction(0.05);
+
+        let right = collapse(SidebarPosition::Right, dims, size, (1920, 1080));
+        assert_eq!((right.width, right.height), (300, 54));
+        let bottom = collapse(SidebarPosition::Bottom, dims, size, (1920, 1080));
+        assert_eq!((bottom.width, bottom.height), (96, 200));
+    }
+
+    #[test]
+    fn test_slots_are_aligned_along_the_edge() {
+        let margins = mock_config().margins.resolve((1920, 1080));
+        let geometry = |align| Geometry {
+            align,
+            ..mock_config().geometry
+        };
+        let dims = [WindowTarget {
+            width: 300,
+            height: 200,
+        }; 2];
+        let offsets = |pos, align| {
+            calculate_slots(pos, &geometry(align), &dims, (1920, 1080), &margins)
+                .iter()
+                .map(|s| s.stack_offset)
+                .collect::<Vec<_>>()
+        };
+
+        // Right: 980 available, the stack takes 200 + 10 + 200 = 410
+        assert_eq!(offsets(SidebarPosition::Right, None), vec![0, 210]);
+        assert_eq!(
+            offsets(SidebarPosition::Right, Some(Align::End)),
+            vec![0, 210]
+        );
+        // Hanging from the top: the last window ends at the top margin
+        assert_eq!(
+            offsets(SidebarPosition::Right, Some(Align::Start)),
+            vec![570, 780]
+        );
+        assert_eq!(
+            offsets(SidebarPosition::Right, Some(Align::Center)),
+            vec![285, 495]
+        );
+
+        // Bottom: 1920 - 10 - 20 = 1890 available, the stack takes 300 + 10 + 300 = 610
+        assert_eq!(offsets(SidebarPosition::Bottom, None), vec![0, 310]);
+        assert_eq!(
+            offsets(SidebarPosition::Bottom, Some(Align::End)),
+            vec![1280, 1590]
+        );
+        assert_eq!(
+            offsets(SidebarPosition::Bottom, Some(Align::Center)),
+            vec![640, 950]
+        );
+    }
+
+    #[test]
+    fn test_grid_layout_positions() {
+        let temp_dir = tempdir().unwrap();
+        let w1 = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let w2 = mock_window(2, false, true, 1, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![w1, w2]);
+
+        let mut config = mock_config();
+        config.geometry.layout = StackLayout::Grid;
+
+        let mut state = AppState::default();
+        for id in [1, 2] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 300,
+                height: 200,
+                ..mock_window_state(id)
+            });
+        }
+
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+
+        let actions = &ctx.socket.sent_actions;
+        // First line at the edge: X = 1920 - 300 - 20 = 1600, Y = 1080 - 200 - 50 = 830
+        assert!(actions.iter().any(|a| matches!(a,
+            Action::MoveFloatingWindow {
+                id: Some(1),
+                x: PositionChange::SetFixed(x),
+                y: PositionChange::SetFixed(y)
+            } if *x == 1600.0 && *y == 830.0
+        )));
+        // Second line next to it: X = 1600 - (300 + 10) = 1290, same Y
+        assert!(actions.iter().any(|a| matches!(a,
+            Action::MoveFloatingWindow {
+                id: Some(2),
+                x: PositionChange::SetFixed(x),
+                y: PositionChange::SetFixed(y)
+            } if *x == 1290.0 && *y == 830.0
+        )));
+    }
+
+    #[test]
+    fn test_accordion_expands_last_focused_window() {
+        let temp_dir = tempdir().unwrap();
+        // Focus is outside the sidebar, window 2 was focused last
+        let windows = (1..=3)
+            .map(|id| mock_window(id, false, true, 1, Some((1.0, 2.0))))
+            .collect();
+        let mock = MockNiri::new(windows);
+
+        let mut config = mock_config();
+        config.geometry.accordion = true;
+
+        let mut state = AppState::default();
+        for id in 1..=3 {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 300,
+                height: 200,
+                ..mock_window_state(id)
+            });
+        }
+        state.sidebar_mut(1).record_focus(2);
+
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+
+        let actions = &ctx.socket.sent_actions;
+        let height_of = |id: u64| {
+            actions.iter().find_map(|a| match a {
+                Action::SetWindowHeight {
+                    id: Some(i),
+                    change: SizeChange::SetFixed(h),
+                } if *i == id => Some(*h),
+                _ => None,
+            })
+        };
+        assert_eq!(height_of(1), Some(40));
+        assert_eq!(height_of(2), Some(200));
+        assert_eq!(height_of(3), Some(40));
+
+        // Stacking uses the collapsed heights:
+        // 1: Y = 1080 - 40 - 50 = 990
+        // 2: Y = 1080 - 200 - 50 - (40 + 10) = 780
+        // 3: Y = 1080 - 40 - 50 - (40 + 10 + 200 + 10) = 730
+        for (id, expected_y) in [(1, 990.0), (2, 780.0), (3, 730.0)] {
+            assert!(actions.iter().any(|a| matches!(a,
+                Action::MoveFloatingWindow {
+                    id: Some(i),
+                    y: PositionChange::SetFixed(y),
+                    ..
+                } if *i == id && *y == expected_y
+            )));
+        }
+    }
+
+    #[test]
+    fn test_reorder_refitted_collapses_windows_niri_reports_collapsed() {
+        let temp_dir = tempdir().unwrap();
+        // Refitting just asked for full heights, but niri still reports the collapsed ones
+        let windows = (1..=2)
+            .map(|id| {
+                let mut window = mock_window(id, id == 2, true, 1, Some((1.0, 2.0)));
+                window.layout.window_size = (300, 40);
+                window
+            })
+            .collect();
+
+        let mut config = mock_config();
+        config.geometry.accordion = true;
+
+        let mut state = AppState::default();
+        for id in 1..=2 {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 300,
+                height: 200,
+                ..mock_window_state(id)
+            });
+        }
+
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: MockNiri::new(windows),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        reorder(&mut ctx).expect("Reorder failed");
+        assert!(
+            !ctx.socket
+                .sent_actions
+                .iter()
+                .any(|a| matches!(a, Action::SetWindowHeight { id: Some(1), .. }))
+        );
+
+        ctx.socket.sent_actions.clear();
+        reorder_refitted(&mut ctx).expect("Reorder failed");
+        assert!(ctx.socket.sent_actions.iter().any(|a| matches!(
+            a,
+            Action::SetWindowHeight {
+                id: Some(1),
+                change: SizeChange::SetFixed(40),
+            }
+        )));
+    }
 }
@@ -0,0 +1,387 @@
+use crate::Ctx;
+use crate::config::{Insert, SidebarPosition, WindowRule};
+use crate::niri::NiriClient;
+use crate::window_rules::{Check, RuleTarget, resolve_rule};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt::{Display, Write};
+
+/// Why a window ends up with the sizes it has
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RulesReport {
+    pub window: RuleWindow,
+    pub rules: Vec<RuleReport>,
+    pub resolved: ResolvedRule,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RuleWindow {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub workspace_id: Option<u64>,
+    pub workspace_name: Option<String>,
+    pub output: Option<String>,
+    /// Whether the window was floating before it joined the sidebar
+    pub is_floating: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RuleReport {
+    /// 1-based position of the rule in the config
+    pub index: usize,
+    pub matched: bool,
+    pub checks: Vec<Check>,
+}
+
+/// A resolved value, sizes in pixels, and the rule it came from, `None` if it is the global default
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Resolved<T> {
+    pub value: T,
+    pub rule: Option<usize>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ResolvedRule {
+    pub width: Resolved<i32>,
+    pub height: Resolved<i32>,
+    pub peek: Resolved<i32>,
+    pub focus_peek: Resolved<i32>,
+    pub auto_add: Resolved<bool>,
+    pub insert: Resolved<Insert>,
+    pub auto_remove: Resolved<bool>,
+    pub remove_when_tiled: Resolved<bool>,
+    pub remove_when_fullscreen: Resolved<bool>,
+}
+
+pub fn rules_test<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window_id: Option<u64>,
+    json: bool,
+) -> Result<String> {
+    let report = explain_rules(ctx, window_id)?;
+    if json {
+        Ok(serde_json::to_string_pretty(&report)?)
+    } else {
+        Ok(format_report(&report))
+    }
+}
+
+/// Checks every window rule against the window with `window_id`, or the focused window
+pub fn explain_rules<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window_id: Option<u64>,
+) -> Result<RulesReport> {
+    let window = match window_id {
+        Some(id) => ctx
+            .socket
+            .get_windows()?
+            .into_iter()
+            .find(|w| w.id == id)
+            .with_context(|| format!("No window with id {}", id))?,
+        None => ctx.socket.get_active_window()?,
+    };
+    let workspaces = ctx.socket.get_workspaces()?;
+    let target = RuleTarget::new(&window, &workspaces, &ctx.state);
+    let output = target
+        .workspace
+        .and_then(|ws| ws.output.clone())
+        .context("Window is not on any output")?;
+
+    let rules = &ctx.config.window_rule;
+    let reports: Vec<RuleReport> = rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            let checks = rule.explain(&target);
+            RuleReport {
+                index: index + 1,
+                matched: checks.iter().all(|check| check.passed),
+                checks,
+            }
+        })
+        .collect();
+
+    // The last matching rule that sets a field is the one it comes from
+    let source = |field: fn(&WindowRule) -> bool| {
+        reports
+            .iter()
+            .zip(rules)
+            .filter(|(report, rule)| report.matched && field(rule))
+            .map(|(report, _)| report.index)
+            .next_back()
+    };
+
+    let layout = ctx.layout_for(Some(&output));
+    let screen = ctx.socket.get_output_dimensions(&output)?;
+    let peek_total = match layout.interaction.position {
+        SidebarPosition::Left | SidebarPosition::Right => screen.0,
+        SidebarPosition::Top | SidebarPosition::Bottom => screen.1,
+    };
+    let effective = resolve_rule(rules, &target);
+    let (width, height) = effective.size(layout.geometry.width, layout.geometry.height, screen);
+
+    let resolved = ResolvedRule {
+        width: Resolved {
+            value: width,
+            rule: source(|r| r.width.is_some()),
+        },
+        height: Resolved {
+            value: height,
+            rule: source(|r| r.height.is_some()),
+        },
+        peek: Resolved {
+            value: effective.peek(layout.interaction.peek, peek_total),
+            rule: source(|r| r.peek.is_some()),
+        },
+        focus_peek: Resolved {
+            value: effective.focus_peek(layout.interaction.get_focus_peek(), peek_total),
+            rule: source(|r| r.focus_peek.is_some()),
+        },
+        auto_add: Resolved {
+            value: effective.auto_add(),
+            rule: source(|r| r.auto_add.is_some()),
+        },
+        insert: Resolved {
+            value: effective.insert(layout.interaction.insert),
+            rule: source(|r| r.insert.is_some()),
+        },
+        auto_remove: Resolved {
+            value: effective.auto_remove(),
+            rule: source(|r| r.auto_remove.is_some()),
+        },
+        remove_when_tiled: Resolved {
+            value: effective.remove_when_tiled.unwrap_or(false),
+            rule: source(|r| r.remove_when_tiled.is_some()),
+        },
+        remove_when_fullscreen: Resolved {
+            value: effective.remove_when_fullscreen.unwrap_or(false),
+            rule: source(|r| r.remove_when_fullscreen.is_some()),
+        },
+    };
+
+    Ok(RulesReport {
+        window: RuleWindow {
+            id: window.id,
+            app_id: window.app_id.clone(),
+            title: window.title.clone(),
+            workspace_id: target.workspace.map(|ws| ws.id),
+            workspace_name: target.workspace.and_then(|ws| ws.name.clone()),
+            output: Some(output),
+            is_floating: target.is_floating,
+        },
+        rules: reports,
+        resolved,
+    })
+}
+
+fn format_report(report: &RulesReport) -> String {
+    let window = &report.window;
+    let mut out = String::new();
+
+    let _ = writeln!(out, "Window {}", window.id);
+    let _ = writeln!(
+        out,
+        "  app_id: {}",
+        window.app_id.as_deref().unwrap_or("<unknown>")
+    );
+    let _ = writeln!(
+        out,
+        "  title: {}",
+        window.title.as_deref().unwrap_or_default()
+    );
+    if let Some(workspace_id) = window.workspace_id {
+        let _ = write!(out, "  workspace: {}", workspace_id);
+        if let Some(name) = &window.workspace_name {
+            let _ = write!(out, " ({})", name);
+        }
+        if let Some(output) = &window.output {
+            let _ = write!(out, " on {}", output);
+        }
+        out.push('\n');
+    }
+    let _ = writeln!(out, "  is_floating: {}", window.is_floating);
+    out.push('\n');
+
+    if report.rules.is_empty() {
+        out.push_str("No window rules configured\n");
+    }
+    for rule in &report.rules {
+        let verdict = if rule.matched { "match" } else { "no match" };
+        let _ = writeln!(out, "Rule {}: {}", rule.index, verdict);
+        if rule.checks.is_empty() {
+            out.push_str("  no conditions, matches every window\n");
+        }
+        for check in &rule.checks {
+            let mark = if check.passed { '+' } else { '-' };
+            let _ = writeln!(out, "  {} {}", mark, check.reason);
+        }
+    }
+    out.push('\n');
+
+    let resolved = &report.resolved;
+    out.push_str("Resolved\n");
+    write_resolved(&mut out, "width", &resolved.width);
+    write_resolved(&mut out, "height", &resolved.height);
+    write_resolved(&mut out, "peek", &resolved.peek);
+    write_resolved(&mut out, "focus_peek", &resolved.focus_peek);
+    write_resolved(&mut out, "auto_add", &resolved.auto_add);
+    write_resolved(&mut out, "insert", &resolved.insert);
+    write_resolved(&mut out, "auto_remove", &resolved.auto_remove);
+    write_resolved(&mut out, "remove_when_tiled", &resolved.remove_when_tiled);
+    write_resolved(
+        &mut out,
+        "remove_when_fullscreen",
+        &resolved.remove_when_fullscreen,
+    );
+
+    out.trim_end().to_string()
+}
+
+fn write_resolved<T: Display>(out: &mut String, name: &str, resolved: &Resolved<T>) {
+    let _ = match resolved.rule {
+        Some(index) => writeln!(out, "  {}: {} (rule {})", name, resolved.value, index),
+        None => writeln!(out, "  {}: {} (default)", name, resolved.value),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WindowMatch;
+    use crate::state::AppState;
+    use crate::test_utils::{MockNiri, mock_config, mock_window};
+    use regex::Regex;
+    use tempfile::tempdir;
+
+    fn ctx_with_rules(rules: Vec<WindowRule>) -> Ctx<MockNiri> {
+        let temp_dir = tempdir().unwrap();
+        let focused = mock_window(1, true, false, 1, None);
+        let mut other = mock_window(2, false, false, 1, None);
+        other.app_id = Some("firefox".into());
+
+        let mut config = mock_config();
+        config.window_rule = rules;
+        Ctx {
+            state: AppState::default(),
+            config,
+            socket: MockNiri::new(vec![focused, other]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        }
+    }
+
+    fn rules() -> Vec<WindowRule> {
+        vec![
+            WindowRule {
+                matches: WindowMatch {
+                    app_id: Some(Regex::new("^test$").unwrap()),
+                    ..Default::default()
+                },
+                width: Some(700.into()),
+                peek: Some(20.into()),
+                ..Default::default()
+            },
+            WindowRule {
+                matches: WindowMatch {
+                    title: Some(Regex::new("Window").unwrap()),
+                    ..Default::default()
+                },
+                exclude: vec![WindowMatch {
+                    app_id: Some(Regex::new("firefox").unwrap()),
+                    ..Default::default()
+                }],
+                peek: Some(30.into()),
+                auto_add: Some(true),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_explain_rules_for_focused_window() {
+        let mut ctx = ctx_with_rules(rules());
+
+        let report = explain_rules(&mut ctx, None).expect("Explain failed");
+
+        assert_eq!(report.window.id, 1);
+        assert_eq!(report.window.output.as_deref(), Some("eDP-1"));
+        assert!(report.rules.iter().all(|r| r.matched));
+        assert_eq!(
+            report.rules[0].checks,
+            vec![Check {
+                passed: true,
+                reason: "app_id \"test\" matches /^test$/".into(),
+            }]
+        );
+
+        let resolved = &report.resolved;
+        assert_eq!(
+            resolved.width,
+            Resolved {
+                value: 700,
+                rule: Some(1),
+            }
+        );
+        assert_eq!(
+            resolved.height,
+            Resolved {
+                value: 200,
+                rule: None,
+            }
+        );
+        // Both rules set a peek, the later one wins
+        assert_eq!(
+            resolved.peek,
+            Resolved {
+                value: 30,
+                rule: Some(2),
+            }
+        );
+        assert_eq!(
+            resolved.auto_add,
+            Resolved {
+                value: true,
+                rule: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn test_explain_rules_reasons_for_no_match() {
+        let mut ctx = ctx_with_rules(rules());
+
+        let report = explain_rules(&mut ctx, Some(2)).expect("Explain failed");
+
+        assert!(!report.rules[0].matched);
+        assert_eq!(
+            report.rules[0].checks[0].reason,
+            "app_id \"firefox\" does not match /^test$/"
+        );
+        assert!(!report.rules[1].matched);
+        assert_eq!(
+            report.rules[1].checks.last().unwrap().reason,
+            "excluded by exclude 1 (app_id \"firefox\" matches /firefox/)"
+        );
+        assert_eq!(report.resolved.width.rule, None);
+        assert!(!report.resolved.auto_add.value);
+
+        assert!(explain_rules(&mut ctx, Some(42)).is_err());
+    }
+
+    #[test]
+    fn test_rules_test_text_and_json() {
+        let mut ctx = ctx_with_rules(rules());
+
+        let text = rules_test(&mut ctx, Some(2), false).unwrap();
+        assert!(text.starts_with("Window 2\n  app_id: firefox\n"));
+        assert!(text.contains("Rule 1: no match\n  - app_id \"firefox\" does not match /^test$/"));
+        assert!(text.ends_with("  remove_when_fullscreen: false (default)"));
+        assert!(text.contains("  auto_add: false (default)\n"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&rules_test(&mut ctx, None, true).unwrap()).unwrap();
+        assert_eq!(json["rules"][1]["matched"], true);
+        assert_eq!(json["resolved"]["width"]["value"], 700);
+        assert_eq!(json["resolved"]["height"]["rule"], serde_json::Value::Null);
+    }
+}
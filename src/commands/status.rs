@@ -0,0 +1,344 @@
+use crate::Ctx;
+use crate::niri::NiriClient;
+use crate::state::WindowState;
+use anyhow::Result;
+use niri_ipc::Window;
+use serde::Serialize;
+use std::fmt::Write;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Status {
+    pub sidebars: Vec<SidebarStatus>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SidebarStatus {
+    pub workspace_id: u64,
+    pub output: Option<String>,
+    pub is_hidden: bool,
+    pub is_flipped: bool,
+    /// Windows from the bottom slot of the stack up
+    pub windows: Vec<WindowStatus>,
+}
+
+/// A tracked window joined with the live data niri has about it
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WindowStatus {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    /// Workspace the window currently lives on, `None` if niri no longer knows the window
+    pub workspace_id: Option<u64>,
+    pub is_focused: bool,
+    /// Size the window is restored to when it leaves the sidebar
+    pub original_size: (i32, i32),
+}
+
+pub fn status<C: NiriClient>(ctx: &mut Ctx<C>, json: bool) -> Result<String> {
+    let status = collect_status(ctx)?;
+    if json {
+        Ok(serde_json::to_string_pretty(&status)?)
+    } else {
+        Ok(format_status(&status))
+    }
+}
+
+pub fn collect_status<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<Status> {
+    let live_windows = ctx.socket.get_windows()?;
+
+    let sidebars = ctx
+        .state
+        .sidebars
+        .iter()
+        .filter(|(_, sidebar)| !sidebar.windows.is_empty())
+        .map(|(workspace_id, sidebar)| {
+            let mut windows: Vec<WindowStatus> = sidebar
+                .windows
+                .iter()
+                .map(|w| window_status(w, live_windows.iter().find(|live| live.id == w.id)))
+                .collect();
+            if sidebar.is_flipped {
+                windows.reverse();
+            }
+            SidebarStatus {
+                workspace_id: *workspace_id,
+                output: sidebar.output.clone(),
+                is_hidden: sidebar.is_hidden,
+                is_flipped: sidebar.is_flipped,
+                windows,
+            }
+        })
+        .collect();
+
+    Ok(Status { sidebars })
+}
+
+/// One line for a waybar `custom` module with `return-type: json`
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WaybarStatus {
+    pub text: String,
+    pub tooltip: String,
+    /// "empty", "hidden" or "visible", usable with `format-icons`
+    pub alt: String,
+    pub class: Vec<String>,
+    pub count: usize,
+}
+
+/// Renders the sidebar of the focused workspace for status bars
+pub fn waybar_status<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<WaybarStatus> {
+    let focused_ws = ctx.socket.get_active_workspace()?.id;
+    let status = collect_status(ctx)?;
+    let sidebar = status
+        .sidebars
+        .iter()
+        .find(|s| s.workspace_id == focused_ws);
+
+    let Some(sidebar) = sidebar else {
+        return Ok(WaybarStatus {
+            text: "0".to_string(),
+            tooltip: "The sidebar is empty".to_string(),
+            alt: "empty".to_string(),
+            class: vec!["empty".to_string()],
+            count: 0,
+        });
+    };
+
+    let alt = if sidebar.is_hidden {
+        "hidden"
+    } else {
+        "visible"
+    };
+    let mut class = vec![alt.to_string()];
+    if sidebar.is_flipped {
+        class.push("flipped".to_string());
+    }
+    if sidebar.windows.iter().any(|w| w.is_focused) {
+        class.push("focused".to_string());
+    }
+
+    let tooltip = sidebar
+        .windows
+        .iter()
+        .map(|w| {
+            format!(
+                "{}{}: {}",
+                if w.is_focused { "> " } else { "" },
+                w.app_id.as_deref().unwrap_or("<unknown>"),
+                w.title.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(WaybarStatus {
+        text: sidebar.windows.len().to_string(),
+        tooltip,
+        alt: alt.to_string(),
+        class,
+        count: sidebar.windows.len(),
+    })
+}
+
+fn window_status(tracked: &WindowState, live: Option<&Window>) -> WindowStatus {
+    WindowStatus {
+        id: tracked.id,
+        app_id: live.map_or(tracked.identity.app_id.clone(), |w| w.app_id.clone()),
+        title: live.map_or(tracked.identity.title.clone(), |w| w.title.clone()),
+        workspace_id: live.and_then(|w| w.workspace_id),
+        is_focused: live.is_some_and(|w| w.is_focused),
+        original_size: (tracked.width, tracked.height),
+    }
+}
+
+fn format_status(status: &Status) -> String {
+    if status.sidebars.is_empty() {
+        return "The sidebar is empty".to_string();
+    }
+
+    let mut out = String::new();
+    for sidebar in &status.sidebars {
+        let mut flags = vec![];
+        if sidebar.is_hidden {
+            flags.push("hidden");
+        }
+        if sidebar.is_flipped {
+            flags.push("flipped");
+        }
+
+        let _ = write!(out, "Workspace {}", sidebar.workspace_id);
+        if let Some(output) = &sidebar.output {
+            let _ = write!(out, " on {}", output);
+        }
+        if !flags.is_empty() {
+            let _ = write!(out, " [{}]", flags.join(", "));
+        }
+        out.push('\n');
+
+        for (index, window) in sidebar.windows.iter().enumerate() {
+            let _ = write!(
+                out,
+                "  {}. {} {} \"{}\"",
+                index + 1,
+                window.id,
+                window.app_id.as_deref().unwrap_or("<unknown>"),
+                window.title.as_deref().unwrap_or_default(),
+            );
+            if window.is_focused {
+                out.push_str(" (focused)");
+            }
+            out.push('\n');
+        }
+    }
+
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AppState;
+    use crate::test_utils::{MockNiri, mock_config, mock_window, mock_window_state};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_status_joins_live_window_data() {
+        let temp_dir = tempdir().unwrap();
+        let mut w1 = mock_window(1, true, true, 1, Some((1.0, 2.0)));
+        w1.app_id = Some("kitty".into());
+        let mock = MockNiri::new(vec![w1]);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(1));
+        // Window 2 is tracked, but niri does not know it anymore
+        state.sidebar_mut(1).windows.push(mock_window_state(2));
+        state.sidebar_mut(1).is_hidden = true;
+        // Empty sidebars are left out
+        state.sidebar_mut(5);
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let status = collect_status(&mut ctx).expect("Status failed");
+
+        assert_eq!(status.sidebars.len(), 1);
+        let sidebar = &status.sidebars[0];
+        assert_eq!(sidebar.workspace_id, 1);
+        assert!(sidebar.is_hidden);
+        assert!(!sidebar.is_flipped);
+
+        assert_eq!(
+            sidebar.windows[0],
+            WindowStatus {
+                id: 1,
+                app_id: Some("kitty".into()),
+                title: Some("Test Window".into()),
+                workspace_id: Some(1),
+                is_focused: true,
+                original_size: (1000, 800),
+            }
+        );
+        assert_eq!(sidebar.windows[1].app_id, None);
+        assert_eq!(sidebar.windows[1].workspace_id, None);
+    }
+
+    #[test]
+    fn test_status_text_and_json() {
+        let temp_dir = tempdir().unwrap();
+        let w1 = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![w1]);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(1));
+        state.sidebar_mut(1).windows.push(mock_window_state(2));
+        state.sidebar_mut(1).is_flipped = true;
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let text = status(&mut ctx, false).unwrap();
+        // Flipped, so the last window of the list is at the bottom
+        assert_eq!(
+            text,
+            "Workspace 1 [flipped]\n  1. 2 <unknown> \"\"\n  2. 1 test \"Test Window\""
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&status(&mut ctx, true).unwrap()).unwrap();
+        assert_eq!(json["sidebars"][0]["is_flipped"], true);
+        assert_eq!(json["sidebars"][0]["windows"][1]["app_id"], "test");
+    }
+
+    #[test]
+    fn test_status_empty() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = Ctx {
+            state: AppState::default(),
+            config: mock_config(),
+            socket: MockNiri::new(vec![]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        assert_eq!(status(&mut ctx, false).unwrap(), "The sidebar is empty");
+    }
+
+    #[test]
+    fn test_waybar_status_for_focused_workspace() {
+        let temp_dir = tempdir().unwrap();
+        let w1 = mock_window(1, true, true, 1, Some((1.0, 2.0)));
+        let mut w2 = mock_window(2, false, true, 1, Some((1.0, 2.0)));
+        w2.app_id = Some("kitty".into());
+        w2.title = Some("Terminal".into());
+        let w3 = mock_window(3, false, true, 2, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![w1, w2, w3]);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(1));
+        state.sidebar_mut(1).windows.push(mock_window_state(2));
+        state.sidebar_mut(1).is_hidden = true;
+        // Not the focused workspace, should not be counted
+        state.sidebar_mut(2).windows.push(mock_window_state(3));
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let waybar = waybar_status(&mut ctx).expect("Waybar status failed");
+
+        assert_eq!(
+            waybar,
+            WaybarStatus {
+                text: "2".into(),
+                tooltip: "> test: Test Window\nkitty: Terminal".into(),
+                alt: "hidden".into(),
+                class: vec!["hidden".into(), "focused".into()],
+                count: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_waybar_status_empty() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = Ctx {
+            state: AppState::default(),
+            config: mock_config(),
+            socket: MockNiri::new(vec![]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let waybar = waybar_status(&mut ctx).unwrap();
+        assert_eq!(waybar.count, 0);
+        assert_eq!(waybar.class, vec!["empty".to_string()]);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::Ctx;
+use crate::commands::reorder;
+use crate::niri::NiriClient;
+use crate::state::{PendingSummon, save_state};
+use anyhow::{Result, bail};
+use niri_ipc::Action;
+
+/// Focuses the sidebar window with `app_id` and reveals its sidebar.
+/// If there is none, runs `exec`. When this runs in the `listen` daemon, the daemon adds
+/// the window to the sidebar if it opens within `SUMMON_TIMEOUT`.
+pub fn summon<C: NiriClient>(ctx: &mut Ctx<C>, app_id: &str, exec: Option<&str>) -> Result<()> {
+    let current_ws = ctx.socket.get_active_workspace()?.id;
+    let windows = ctx.socket.get_windows()?;
+    let matching: Vec<u64> = windows
+        .iter()
+        .filter(|w| w.app_id.as_deref() == Some(app_id) && ctx.state.is_tracked(w.id))
+        .map(|w| w.id)
+        .collect();
+    // Prefer the sidebar of the current workspace
+    let found = matching
+        .iter()
+        .filter_map(|id| ctx.state.find_window(*id).map(|(ws, _)| (*id, ws)))
+        .min_by_key(|(_, ws)| *ws != current_ws);
+
+    if let Some((id, ws)) = found {
+        ctx.state.sidebar_mut(ws).is_hidden = false;
+        save_state(&ctx.state, &ctx.cache_dir)?;
+        let _ = ctx.socket.send_action(Action::FocusWindow { id });
+        reorder(ctx)?;
+        return Ok(());
+    }
+
+    let Some(command) = exec else {
+        bail!("No sidebar window with app_id {}", app_id);
+    };
+    if ctx.state.listening {
+        ctx.state
+            .pending_summons
+            .retain(|pending| pending.app_id != app_id && !pending.is_expired());
+        ctx.state.pending_summons.push(PendingSummon::new(app_id));
+    }
+    ctx.socket.send_action(Action::SpawnSh {
+        command: command.to_string(),
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{AppState, WindowState};
+    use crate::test_utils::{MockNiri, mock_ctx, mock_window, mock_window_state};
+    use tempfile::{TempDir, tempdir};
+
+    fn summon_ctx(temp_dir: &TempDir) -> Ctx<MockNiri> {
+        let mut scratch = mock_window(100, false, true, 1, Some((1.0, 2.0)));
+        scratch.app_id = Some("kitty-scratch".into());
+        let tiled = mock_window(200, true, false, 1, None);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 300,
+            height: 500,
+            ..mock_window_state(100)
+        });
+        state.sidebar_mut(1).is_hidden = true;
+
+        mock_ctx(temp_dir.path(), state, vec![scratch, tiled])
+    }
+
+    #[test]
+    fn test_summon_focuses_and_reveals_sidebar_window() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = summon_ctx(&temp_dir);
+
+        summon(
+            &mut ctx,
+            "kitty-scratch",
+            Some("kitty --class kitty-scratch"),
+        )
+        .unwrap();
+
+        assert!(!ctx.state.sidebar(1).unwrap().is_hidden);
+        assert!(ctx.state.pending_summons.is_empty());
+        let actions = &ctx.socket.sent_actions;
+        assert!(matches!(actions[0], Action::FocusWindow { id: 100 }));
+        assert!(!actions.iter().any(|a| matches!(a, Action::SpawnSh { .. })));
+    }
+
+    #[test]
+    fn test_summon_launches_missing_window() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = summon_ctx(&temp_dir);
+
+        // Without a daemon nothing would ever pick up the window
+        summon(&mut ctx, "test", Some("foot --app-id test")).unwrap();
+        assert!(ctx.state.pending_summons.is_empty());
+
+        // The tiled window has the app id too, but it is not in the sidebar
+        ctx.state.listening = true;
+        summon(&mut ctx, "test", Some("foot --app-id test")).unwrap();
+
+        let pending: Vec<&str> = ctx
+            .state
+            .pending_summons
+            .iter()
+            .map(|p| p.app_id.as_str())
+            .collect();
+        assert_eq!(pending, vec!["test"]);
+        assert!(matches!(
+            ctx.socket.sent_actions.as_slice(),
+            [Action::SpawnSh { command }, Action::SpawnSh { .. }] if command == "foot --app-id test"
+        ));
+
+        assert!(summon(&mut ctx, "other", None).is_err());
+    }
+}
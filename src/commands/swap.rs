@@ -0,0 +1,174 @@
+use crate::Ctx;
+use crate::commands::reorder;
+use crate::commands::togglewindow::{add_to_sidebar, remove_from_sidebar, window_workspace};
+use crate::niri::NiriClient;
+use crate::state::save_state;
+use anyhow::{Context, Result, bail};
+use niri_ipc::Action;
+
+/// Trades places between the focused tiled window and the sidebar window in slot `index`,
+/// or the most recently focused sidebar window if no index is given
+pub fn swap<C: NiriClient>(ctx: &mut Ctx<C>, index: Option<usize>) -> Result<()> {
+    let windows = ctx.socket.get_windows()?;
+    let tiled = windows
+        .iter()
+        .find(|w| w.is_focused)
+        .context("No window focused")?;
+    if tiled.is_floating || ctx.state.is_tracked(tiled.id) {
+        bail!("Focus a tiled window to swap it with a sidebar window");
+    }
+
+    let workspace = window_workspace(ctx, tiled)?;
+    let sidebar = ctx
+        .state
+        .sidebar(workspace.id)
+        .filter(|s| !s.windows.is_empty())
+        .context("The sidebar of this workspace is empty")?;
+    let slot = match index {
+        Some(index) => sidebar
+            .slot_index(index)
+            .with_context(|| format!("There is no window in slot {} of the sidebar", index))?,
+        None => sidebar
+            .last_focused()
+            .and_then(|id| sidebar.windows.iter().position(|w| w.id == id))
+            .context(
+                "No sidebar window was focused yet, pass the slot of the window to swap with",
+            )?,
+    };
+    let sidebar_id = sidebar.windows[slot].id;
+    let promoted = windows
+        .iter()
+        .find(|w| w.id == sidebar_id)
+        .context("The sidebar window is not open anymore")?;
+    let column = tiled
+        .layout
+        .pos_in_scrolling_layout
+        .map(|(column, _)| column);
+
+    remove_from_sidebar(ctx, promoted)?;
+    // Windows that were floating before they joined the sidebar would float again
+    let _ = ctx.socket.send_action(Action::MoveWindowToTiling {
+        id: Some(promoted.id),
+    });
+
+    add_to_sidebar(ctx, tiled)?;
+    if let Some(state) = ctx.state.remove_window(tiled.id) {
+        ctx.state.insert_window(workspace.id, slot, state);
+    }
+    // Swapping again without a slot trades the two windows back
+    ctx.state.sidebar_mut(workspace.id).record_focus(tiled.id);
+
+    let _ = ctx
+        .socket
+        .send_action(Action::FocusWindow { id: promoted.id });
+    if let Some(index) = column {
+        let _ = ctx.socket.send_action(Action::MoveColumnToIndex { index });
+    }
+
+    save_state(&ctx.state, &ctx.cache_dir)?;
+    reorder(ctx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AppState;
+    use crate::test_utils::{MockNiri, mock_ctx, mock_window, mock_window_state};
+    use niri_ipc::Window;
+    use tempfile::{TempDir, tempdir};
+
+    fn swap_ctx(temp_dir: &TempDir, tiled: Window) -> Ctx<MockNiri> {
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(mock_window_state(1));
+        state.sidebar_mut(1).windows.push(mock_window_state(2));
+
+        let windows = vec![
+            mock_window(1, false, true, 1, Some((1.0, 2.0))),
+            mock_window(2, false, true, 1, Some((1.0, 2.0))),
+            tiled,
+        ];
+        mock_ctx(temp_dir.path(), state, windows)
+    }
+
+    fn order(ctx: &Ctx<MockNiri>) -> Vec<u64> {
+        ctx.state.windows(1).iter().map(|w| w.id).collect()
+    }
+
+    #[test]
+    fn test_swap_with_last_focused() {
+        let temp_dir = tempdir().unwrap();
+        let mut tiled = mock_window(100, true, false, 1, None);
+        tiled.layout.pos_in_scrolling_layout = Some((3, 1));
+        let mut ctx = swap_ctx(&temp_dir, tiled);
+        ctx.state.sidebar_mut(1).record_focus(2);
+
+        swap(&mut ctx, None).expect("Swap failed");
+
+        // The tiled window takes the slot, the sidebar window its column
+        assert_eq!(order(&ctx), vec![1, 100]);
+        assert_eq!(ctx.state.sidebar(1).unwrap().last_focused(), Some(100));
+        assert!(ctx.state.ignored_windows.contains(&2));
+
+        let actions = &ctx.socket.sent_actions;
+        let position = |wanted: fn(&Action) -> bool| actions.iter().position(wanted).unwrap();
+        let tiling = position(|a| matches!(a, Action::MoveWindowToTiling { id: Some(2) }));
+        let floating = position(|a| matches!(a, Action::ToggleWindowFloating { id: Some(100) }));
+        let focus = position(|a| matches!(a, Action::FocusWindow { id: 2 }));
+        let column = position(|a| matches!(a, Action::MoveColumnToIndex { index: 3 }));
+        assert!(tiling < floating && floating < focus && focus < column);
+    }
+
+    #[test]
+    fn test_swap_by_index() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = swap_ctx(&temp_dir, mock_window(100, true, false, 1, None));
+
+        swap(&mut ctx, Some(1)).expect("Swap failed");
+        assert_eq!(order(&ctx), vec![100, 2]);
+        // Without a known column the window stays where niri put it
+        assert!(
+            !ctx.socket
+                .sent_actions
+                .iter()
+                .any(|a| matches!(a, Action::MoveColumnToIndex { .. }))
+        );
+
+        // Slot 1 of a flipped sidebar is the end of the list
+        let mut ctx = swap_ctx(&temp_dir, mock_window(100, true, false, 1, None));
+        ctx.state.sidebar_mut(1).is_flipped = true;
+        swap(&mut ctx, Some(1)).expect("Swap failed");
+        assert_eq!(order(&ctx), vec![1, 100]);
+    }
+
+    #[test]
+    fn test_swap_errors() {
+        let temp_dir = tempdir().unwrap();
+        let mut ctx = swap_ctx(&temp_dir, mock_window(100, true, false, 1, None));
+
+        let err = swap(&mut ctx, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No sidebar window was focused yet, pass the slot of the window to swap with"
+        );
+        let err = swap(&mut ctx, Some(3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "There is no window in slot 3 of the sidebar"
+        );
+        // Sent over the control socket the index is not range checked
+        let err = swap(&mut ctx, Some(0)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "There is no window in slot 0 of the sidebar"
+        );
+
+        let mut ctx = swap_ctx(&temp_dir, mock_window(100, true, true, 1, None));
+        let err = swap(&mut ctx, Some(1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Focus a tiled window to swap it with a sidebar window"
+        );
+        assert_eq!(order(&ctx), vec![1, 2]);
+    }
+}
@@ -1,17 +1,16 @@
 use crate::Ctx;
 use crate::commands::reorder;
 use crate::niri::NiriClient;
-use crate::state::{WindowState, save_state};
-use crate::window_rules::resolve_window_size;
+use crate::state::{WindowIdentity, WindowState, save_state};
+use crate::window_rules::{RuleTarget, resolve_rule};
 use anyhow::{Context, Result};
-use niri_ipc::{Action, SizeChange, Window};
+use niri_ipc::{Action, SizeChange, Window, Workspace};
+use std::slice;
 
 pub fn toggle_window<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
     let focused = ctx.socket.get_active_window()?;
 
-    let is_tracked = ctx.state.windows.iter().any(|w| w.id == focused.id);
-
-    if is_tracked {
+    if ctx.state.is_tracked(focused.id) {
         remove_from_sidebar(ctx, &focused)?;
     } else {
         add_to_sidebar(ctx, &focused)?;
@@ -24,6 +23,8 @@ pub fn toggle_window<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
 }
 
 pub fn add_to_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Result<()> {
+    let workspace = window_workspace(ctx, window)?;
+
     let (width, height) = window.layout.window_size;
     let w_state = WindowState {
         id: window.id,
@@ -31,8 +32,49 @@ pub fn add_to_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Resul
         height,
         is_floating: window.is_floating,
         position: window.layout.tile_pos_in_workspace_view,
+        identity: WindowIdentity::of(window),
     };
-    ctx.state.windows.push(w_state);
+    let target = RuleTarget::new(window, slice::from_ref(&workspace), &ctx.state);
+    let default_insert = ctx
+        .layout_for(workspace.output.as_deref())
+        .interaction
+        .insert;
+    let insert = resolve_rule(&ctx.config.window_rule, &target).insert(default_insert);
+    let index = ctx.state.sidebar_mut(workspace.id).insert_index(insert);
+    ctx.state.insert_window(workspace.id, index, w_state);
+    ctx.state.ejected_windows.retain(|id| *id != window.id);
+
+    fit_to_sidebar(ctx, window, &workspace)?;
+
+    Ok(())
+}
+
+/// The workspace `window` is on, or the focused one if it is not on any
+pub(crate) fn window_workspace<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window: &Window,
+) -> Result<Workspace> {
+    let workspaces = ctx.socket.get_workspaces()?;
+    let index = workspaces
+        .iter()
+        .position(|ws| Some(ws.id) == window.workspace_id)
+        .or_else(|| workspaces.iter().position(|ws| ws.is_focused))
+        .context("No workspace found for window")?;
+    Ok(workspaces[index].clone())
+}
+
+/// Makes `window` floating and gives it the size the sidebar on `workspace` wants for it
+pub(crate) fn fit_to_sidebar<C: NiriClient>(
+    ctx: &mut Ctx<C>,
+    window: &Window,
+    workspace: &Workspace,
+) -> Result<()> {
+    let output = workspace
+        .output
+        .as_deref()
+        .context("Workspace is not on any output")?;
+    let layout = ctx.layout_for(Some(output));
+    let screen = ctx.socket.get_output_dimensions(output)?;
 
     if !window.is_floating {
         let _ = ctx.socket.send_action(Action::ToggleWindowFloating {
@@ -40,11 +82,11 @@ pub fn add_to_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Resul
         });
     }
 
-    let (target_width, target_height) = resolve_window_size(
-        &ctx.config.window_rule,
-        window,
-        ctx.config.geometry.width,
-        ctx.config.geometry.height,
+    let target = RuleTarget::new(window, slice::from_ref(workspace), &ctx.state);
+    let (target_width, target_height) = resolve_rule(&ctx.config.window_rule, &target).size(
+        layout.geometry.width,
+        layout.geometry.height,
+        screen,
     );
 
     let _ = ctx.socket.send_action(Action::SetWindowWidth {
@@ -60,15 +102,28 @@ pub fn add_to_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Resul
     Ok(())
 }
 
-fn remove_from_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Result<()> {
-    let index = ctx
+/// Gives every sidebar window the size the current settings want for it
+pub(crate) fn refit_sidebar_windows<C: NiriClient>(ctx: &mut Ctx<C>) -> Result<()> {
+    let workspaces = ctx.socket.get_workspaces()?;
+    for window in ctx.socket.get_windows()? {
+        if !window.is_floating || !ctx.state.is_tracked(window.id) {
+            continue;
+        }
+        if let Some(workspace) = workspaces
+            .iter()
+            .find(|ws| Some(ws.id) == window.workspace_id)
+        {
+            fit_to_sidebar(ctx, &window, workspace)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_from_sidebar<C: NiriClient>(ctx: &mut Ctx<C>, window: &Window) -> Result<()> {
+    let w_state = ctx
         .state
-        .windows
-        .iter()
-        .position(|w| w.id == window.id)
+        .remove_window(window.id)
         .context("Window was not found in sidebar state")?;
-
-    let w_state = ctx.state.windows.remove(index);
     ctx.state.ignored_windows.push(w_state.id);
 
     let _ = ctx.socket.send_action(Action::SetWindowWidth {
@@ -108,7 +163,7 @@ mod tests {
     use super::*;
     use crate::config::Config;
     use crate::state::AppState;
-    use crate::test_utils::{MockNiri, mock_config, mock_window};
+    use crate::test_utils::{MockNiri, mock_config, mock_window, mock_window_state};
 
     #[test]
     fn test_add_to_sidebar_tiled() {
@@ -128,8 +183,8 @@ mod tests {
         toggle_window(&mut ctx).expect("Command failed");
 
         // Window 100 should be in the sidebar list with original size (1000x800)
-        assert_eq!(ctx.state.windows.len(), 1);
-        let w_state = &ctx.state.windows[0];
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        let w_state = &ctx.state.windows(1)[0];
         assert_eq!(w_state.id, 100);
         assert_eq!(w_state.width, 1000);
         assert_eq!(w_state.height, 800);
@@ -173,8 +228,8 @@ mod tests {
         toggle_window(&mut ctx).expect("Command failed");
 
         // Window 100 should be in the sidebar list with original size (1000x800)
-        assert_eq!(ctx.state.windows.len(), 1);
-        let w_state = &ctx.state.windows[0];
+        assert_eq!(ctx.state.windows(1).len(), 1);
+        let w_state = &ctx.state.windows(1)[0];
         assert_eq!(w_state.id, 100);
         assert_eq!(w_state.width, 1000);
         assert_eq!(w_state.height, 800);
@@ -210,12 +265,15 @@ mod tests {
         let mock = MockNiri::new(vec![win]);
         let mut config = mock_config();
 
-        use crate::config::WindowRule;
+        use crate::config::{WindowMatch, WindowRule};
         use regex::Regex;
         config.window_rule = vec![WindowRule {
-            app_id: Some(Regex::new("special").unwrap()),
-            width: Some(500),
-            height: Some(600),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("special").unwrap()),
+                ..Default::default()
+            },
+            width: Some(500.into()),
+            height: Some(600.into()),
             ..Default::default()
         }];
         let mut ctx = Ctx {
@@ -226,7 +284,7 @@ mod tests {
         };
         toggle_window(&mut ctx).expect("Command failed");
 
-        assert_eq!(ctx.state.windows.len(), 1);
+        assert_eq!(ctx.state.windows(1).len(), 1);
 
         let actions = &ctx.socket.sent_actions;
         // Should set width to 500 (Rule width), not 300 (Config default)
@@ -248,6 +306,58 @@ mod tests {
         )));
     }
 
+    #[test]
+    fn test_add_to_sidebar_insert_position() {
+        use crate::config::{Insert, WindowMatch, WindowRule};
+        use regex::Regex;
+
+        let temp_dir = tempdir().unwrap();
+        let mut player = mock_window(100, true, false, 1, None);
+        player.app_id = Some("player".into());
+        let other = mock_window(200, false, false, 1, None);
+        let late = mock_window(300, false, false, 1, None);
+
+        let mut state = AppState::default();
+        for id in [1, 2, 3] {
+            state.sidebar_mut(1).windows.push(WindowState {
+                width: 100,
+                height: 100,
+                is_floating: true,
+                ..mock_window_state(id)
+            });
+        }
+
+        let mut config = mock_config();
+        config.interaction.insert = Insert::Index(2);
+        config.window_rule = vec![WindowRule {
+            matches: WindowMatch {
+                app_id: Some(Regex::new("^player$").unwrap()),
+                ..Default::default()
+            },
+            insert: Some(Insert::Bottom),
+            ..Default::default()
+        }];
+        let mut ctx = Ctx {
+            state,
+            config,
+            socket: MockNiri::new(vec![player.clone(), other.clone(), late.clone()]),
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        add_to_sidebar(&mut ctx, &player).expect("Add failed");
+        add_to_sidebar(&mut ctx, &other).expect("Add failed");
+
+        let ids: Vec<u64> = ctx.state.windows(1).iter().map(|w| w.id).collect();
+        // The rule puts the player at the bottom, the global default puts the other window above it
+        assert_eq!(ids, vec![100, 200, 1, 2, 3]);
+
+        // A flipped sidebar shows the end of the list at the bottom, slot 2 is next to it
+        ctx.state.sidebar_mut(1).is_flipped = true;
+        add_to_sidebar(&mut ctx, &late).expect("Add failed");
+        let ids: Vec<u64> = ctx.state.windows(1).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![100, 200, 1, 2, 300, 3]);
+    }
+
     #[test]
     fn test_remove_from_sidebar_floating_restore_pos() {
         let temp_dir = tempdir().unwrap();
@@ -261,8 +371,9 @@ mod tests {
             height: 800,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mut ctx = Ctx {
             state,
@@ -274,7 +385,7 @@ mod tests {
         toggle_window(&mut ctx).expect("Command failed");
 
         // Should be empty now
-        assert!(ctx.state.windows.is_empty());
+        assert!(ctx.state.windows(1).is_empty());
 
         // Should be added to ignore list
         assert!(ctx.state.ignored_windows[0] == 100);
@@ -324,8 +435,9 @@ mod tests {
             height: 800,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
-        state.windows.push(w1);
+        state.sidebar_mut(1).windows.push(w1);
 
         let mut ctx = Ctx {
             state,
@@ -337,7 +449,7 @@ mod tests {
         toggle_window(&mut ctx).expect("Command failed");
 
         // Should be empty now
-        assert!(ctx.state.windows.is_empty());
+        assert!(ctx.state.windows(1).is_empty());
 
         // Should be added to ignore list
         assert!(ctx.state.ignored_windows[0] == 100);
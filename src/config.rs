@@ -1,8 +1,11 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Table};
 
 pub const DEFAULT_CONFIG_STR: &str = include_str!("../default_config.toml");
 
@@ -15,50 +18,408 @@ pub enum SidebarPosition {
     Bottom,
 }
 
+/// A length in pixels, or a fraction of the output size written as `"25%"` or `0.3`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawSize", into = "RawSize")]
+pub enum Size {
+    Pixels(i32),
+    Fraction(f64),
+}
+
+impl Size {
+    /// The size in pixels, relative to `total` pixels of the output
+    pub fn resolve(self, total: i32) -> i32 {
+        match self {
+            Size::Pixels(px) => px,
+            Size::Fraction(fraction) => (fraction * total as f64).round() as i32,
+        }
+    }
+
+    fn is_positive(self) -> bool {
+        match self {
+            Size::Pixels(px) => px > 0,
+            Size::Fraction(fraction) => fraction > 0.0,
+        }
+    }
+
+    fn is_negative(self) -> bool {
+        match self {
+            Size::Pixels(px) => px < 0,
+            Size::Fraction(fraction) => fraction < 0.0,
+        }
+    }
+
+    /// Only sizes of the same kind can be compared without knowing the output size
+    fn exceeds(self, other: Size) -> bool {
+        match (self, other) {
+            (Size::Pixels(a), Size::Pixels(b)) => a > b,
+            (Size::Fraction(a), Size::Fraction(b)) => a > b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Size::Pixels(px) => write!(f, "{}", px),
+            // Rounded so 0.07 shows as 7% and not as 7.000000000000001%
+            Size::Fraction(fraction) => write!(f, "{}%", (fraction * 1e8).round() / 1e6),
+        }
+    }
+}
+
+impl From<i32> for Size {
+    fn from(px: i32) -> Self {
+        Size::Pixels(px)
+    }
+}
+
+/// Every way a size can be written in the config
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawSize {
+    Pixels(i32),
+    Fraction(f64),
+    Text(String),
+}
+
+impl TryFrom<RawSize> for Size {
+    type Error = String;
+
+    fn try_from(raw: RawSize) -> Result<Self, Self::Error> {
+        match raw {
+            RawSize::Pixels(px) => Ok(Size::Pixels(px)),
+            RawSize::Fraction(fraction) => Ok(Size::Fraction(fraction)),
+            RawSize::Text(text) => {
+                let text = text.trim();
+                let parsed = match text.strip_suffix('%') {
+                    Some(percent) => percent
+                        .trim()
+                        .parse::<f64>()
+                        .map(|p| Size::Fraction(p / 100.0)),
+                    None => text
+                        .parse::<i32>()
+                        .map(Size::Pixels)
+                        .or_else(|_| text.parse::<f64>().map(Size::Fraction)),
+                };
+                parsed.map_err(|_| {
+                    format!("invalid size \"{}\", expected pixels, a percentage like \"25%\" or a fraction like 0.3", text)
+                })
+            }
+        }
+    }
+}
+
+impl From<Size> for RawSize {
+    fn from(size: Size) -> Self {
+        match size {
+            Size::Pixels(px) => RawSize::Pixels(px),
+            Size::Fraction(fraction) => RawSize::Fraction(fraction),
+        }
+    }
+}
+
+/// Where a window joins the sidebar, written as `"top"`, `"bottom"` or a slot number.
+/// These are stack order, not screen positions: stacks grow from the bottom, so slot 1 is
+/// the bottom and the top is the end of the list, whichever edge the sidebar is on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawInsert", into = "RawInsert")]
+pub enum Insert {
+    #[default]
+    Top,
+    Bottom,
+    Index(usize),
+}
+
+impl Insert {
+    /// The position in a sidebar holding `len` windows
+    pub fn index(self, len: usize) -> usize {
+        match self {
+            Insert::Top => len,
+            Insert::Bottom => 0,
+            Insert::Index(slot) => slot.saturating_sub(1).min(len),
+        }
+    }
+}
+
+impl Display for Insert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Insert::Top => write!(f, "top"),
+            Insert::Bottom => write!(f, "bottom"),
+            Insert::Index(slot) => write!(f, "{}", slot),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawInsert {
+    Index(usize),
+    Text(String),
+}
+
+impl TryFrom<RawInsert> for Insert {
+    type Error = String;
+
+    fn try_from(raw: RawInsert) -> Result<Self, Self::Error> {
+        match raw {
+            RawInsert::Index(0) => Err("insert slots start at 1".to_string()),
+            RawInsert::Index(slot) => Ok(Insert::Index(slot)),
+            RawInsert::Text(text) => match text.as_str() {
+                "top" => Ok(Insert::Top),
+                "bottom" => Ok(Insert::Bottom),
+                _ => Err(format!(
+                    "invalid insert \"{}\", expected \"top\", \"bottom\" or a slot number",
+                    text
+                )),
+            },
+        }
+    }
+}
+
+impl From<Insert> for RawInsert {
+    fn from(insert: Insert) -> Self {
+        match insert {
+            Insert::Index(slot) => RawInsert::Index(slot),
+            other => RawInsert::Text(other.to_string()),
+        }
+    }
+}
+
+/// Margins resolved to pixels for one output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelMargins {
+    pub top: i32,
+    pub right: i32,
+    pub left: i32,
+    pub bottom: i32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub geometry: Geometry,
     pub margins: Margins,
     pub interaction: Interaction,
+    /// Per-output overrides keyed by output name, e.g. `[output."eDP-1"]`
+    #[serde(default)]
+    pub output: BTreeMap<String, OutputConfig>,
     #[serde(default)]
     pub window_rule: Vec<WindowRule>,
+    /// Named sets of overrides that can be switched to with `niri-sidebar profile`
+    #[serde(default)]
+    pub profile: BTreeMap<String, ProfileConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How the sidebar windows are arranged along the screen edge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackLayout {
+    /// A single line of windows
+    #[default]
+    Stack,
+    /// `columns` lines next to each other, filled one row at a time
+    Grid,
+    /// A single line that continues in a new line once it reaches the end of the screen
+    Wrap,
+}
+
+/// Where the stack sits along its screen edge, from the top or left end of the edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Geometry {
-    pub width: i32,
-    pub height: i32,
-    pub gap: i32,
+    pub width: Size,
+    pub height: Size,
+    /// Measured along the screen edge when relative
+    pub gap: Size,
+    #[serde(default)]
+    pub layout: StackLayout,
+    /// Number of lines used by the grid layout
+    #[serde(default = "default_columns")]
+    pub columns: usize,
+    /// Only expand the focused window, the others shrink to `collapsed`
+    #[serde(default)]
+    pub accordion: bool,
+    /// Size of collapsed windows along the screen edge in accordion mode,
+    /// measured along the screen edge when relative
+    #[serde(default = "default_collapsed")]
+    pub collapsed: Size,
+    /// Defaults to the bottom for left and right sidebars and to the left for top and bottom ones
+    #[serde(default)]
+    pub align: Option<Align>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Margins {
     #[serde(default = "default_margin")]
-    pub top: i32,
+    pub top: Size,
     #[serde(default = "default_margin")]
-    pub right: i32,
+    pub right: Size,
     #[serde(default = "default_margin")]
-    pub left: i32,
+    pub left: Size,
     #[serde(default = "default_margin")]
-    pub bottom: i32,
+    pub bottom: Size,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Margins {
+    /// Resolves relative margins against a `screen` sized output
+    pub fn resolve(&self, (sw, sh): (i32, i32)) -> PixelMargins {
+        PixelMargins {
+            top: self.top.resolve(sh),
+            right: self.right.resolve(sw),
+            left: self.left.resolve(sw),
+            bottom: self.bottom.resolve(sh),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Interaction {
-    pub peek: i32,
-    pub focus_peek: Option<i32>,
+    pub peek: Size,
+    pub focus_peek: Option<Size>,
     #[serde(default = "default_position")]
     pub position: SidebarPosition,
     #[serde(default = "default_sticky")]
     pub sticky: bool,
+    /// Where new windows join the sidebar unless a window rule says otherwise
+    #[serde(default)]
+    pub insert: Insert,
 }
 
 impl Interaction {
-    pub fn get_focus_peek(&self) -> i32 {
+    pub fn get_focus_peek(&self) -> Size {
         self.focus_peek.unwrap_or(self.peek)
     }
 }
 
+/// Settings for a single output, any field left out falls back to the global config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputConfig {
+    pub position: Option<SidebarPosition>,
+    #[serde(default)]
+    pub geometry: GeometryOverride,
+    #[serde(default)]
+    pub margins: MarginsOverride,
+}
+
+/// Overrides for the global settings while the profile is active
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default)]
+    pub geometry: GeometryOverride,
+    #[serde(default)]
+    pub margins: MarginsOverride,
+    #[serde(default)]
+    pub interaction: InteractionOverride,
+    /// Hide or show all sidebars when switching to the profile
+    pub hidden: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeometryOverride {
+    pub width: Option<Size>,
+    pub height: Option<Size>,
+    pub gap: Option<Size>,
+    pub layout: Option<StackLayout>,
+    pub columns: Option<usize>,
+    pub accordion: Option<bool>,
+    pub collapsed: Option<Size>,
+    pub align: Option<Align>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarginsOverride {
+    pub top: Option<Size>,
+    pub right: Option<Size>,
+    pub left: Option<Size>,
+    pub bottom: Option<Size>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InteractionOverride {
+    pub peek: Option<Size>,
+    pub focus_peek: Option<Size>,
+    pub position: Option<SidebarPosition>,
+    pub sticky: Option<bool>,
+    pub insert: Option<Insert>,
+}
+
+impl GeometryOverride {
+    fn apply(&self, geometry: &mut Geometry) {
+        geometry.width = self.width.unwrap_or(geometry.width);
+        geometry.height = self.height.unwrap_or(geometry.height);
+        geometry.gap = self.gap.unwrap_or(geometry.gap);
+        geometry.layout = self.layout.unwrap_or(geometry.layout);
+        geometry.columns = self.columns.unwrap_or(geometry.columns);
+        geometry.accordion = self.accordion.unwrap_or(geometry.accordion);
+        geometry.collapsed = self.collapsed.unwrap_or(geometry.collapsed);
+        geometry.align = self.align.or(geometry.align);
+    }
+}
+
+impl MarginsOverride {
+    fn apply(&self, margins: &mut Margins) {
+        margins.top = self.top.unwrap_or(margins.top);
+        margins.right = self.right.unwrap_or(margins.right);
+        margins.left = self.left.unwrap_or(margins.left);
+        margins.bottom = self.bottom.unwrap_or(margins.bottom);
+    }
+}
+
+impl InteractionOverride {
+    fn apply(&self, interaction: &mut Interaction) {
+        interaction.peek = self.peek.unwrap_or(interaction.peek);
+        interaction.focus_peek = self.focus_peek.or(interaction.focus_peek);
+        interaction.position = self.position.unwrap_or(interaction.position);
+        interaction.sticky = self.sticky.unwrap_or(interaction.sticky);
+        interaction.insert = self.insert.unwrap_or(interaction.insert);
+    }
+}
+
+/// The sidebar settings that apply on one specific output
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    pub geometry: Geometry,
+    pub margins: Margins,
+    pub interaction: Interaction,
+}
+
+impl Config {
+    /// Resolves the global settings combined with the overrides of the active `profile`
+    /// and then those for `output`. Unknown profiles are ignored.
+    pub fn layout_for(&self, profile: Option<&str>, output: Option<&str>) -> OutputLayout {
+        let mut layout = OutputLayout {
+            geometry: self.geometry.clone(),
+            margins: self.margins.clone(),
+            interaction: self.interaction.clone(),
+        };
+
+        if let Some(overrides) = profile.and_then(|name| self.profile.get(name)) {
+            overrides.geometry.apply(&mut layout.geometry);
+            overrides.margins.apply(&mut layout.margins);
+            overrides.interaction.apply(&mut layout.interaction);
+        }
+
+        if let Some(overrides) = output.and_then(|name| self.output.get(name)) {
+            overrides.geometry.apply(&mut layout.geometry);
+            overrides.margins.apply(&mut layout.margins);
+            if let Some(position) = overrides.position {
+                layout.interaction.position = position;
+            }
+        }
+
+        layout
+    }
+}
+
 fn default_sticky() -> bool {
     false
 }
@@ -67,22 +428,59 @@ fn default_position() -> SidebarPosition {
     SidebarPosition::Right
 }
 
-fn default_margin() -> i32 {
-    0
+fn default_margin() -> Size {
+    Size::Pixels(0)
 }
 
+fn default_columns() -> usize {
+    2
+}
+
+fn default_collapsed() -> Size {
+    Size::Pixels(40)
+}
+
+/// Conditions a window has to meet, every condition that is set has to hold
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct WindowRule {
+pub struct WindowMatch {
     #[serde(default, with = "serde_regex")]
     pub app_id: Option<Regex>,
     #[serde(default, with = "serde_regex")]
     pub title: Option<Regex>,
-    pub width: Option<i32>,
-    pub height: Option<i32>,
-    pub peek: Option<i32>,
-    pub focus_peek: Option<i32>,
+    /// Regex for the name of the workspace the window is on
+    #[serde(default, with = "serde_regex")]
+    pub workspace: Option<Regex>,
+    /// Index of the workspace on its output, as shown by `niri msg workspaces`
+    pub workspace_index: Option<u8>,
+    /// Regex for the name of the output the window is on
+    #[serde(default, with = "serde_regex")]
+    pub output: Option<Regex>,
+    /// Whether the window was floating before it joined the sidebar
+    pub is_floating: Option<bool>,
+    pub is_urgent: Option<bool>,
+    pub pid: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct WindowRule {
+    #[serde(flatten)]
+    pub matches: WindowMatch,
+    /// The rule does not apply to windows matching any of these
     #[serde(default)]
-    pub auto_add: bool,
+    pub exclude: Vec<WindowMatch>,
+    pub width: Option<Size>,
+    pub height: Option<Size>,
+    pub peek: Option<Size>,
+    pub focus_peek: Option<Size>,
+    pub auto_add: Option<bool>,
+    /// Where matching windows join the sidebar
+    pub insert: Option<Insert>,
+    /// Push matching windows out of the sidebar
+    pub auto_remove: Option<bool>,
+    /// Push windows out of the sidebar when they are moved into the tiling layout
+    pub remove_when_tiled: Option<bool>,
+    /// Push windows out of the sidebar when they cover the whole output
+    pub remove_when_fullscreen: Option<bool>,
 }
 
 impl Default for Config {
@@ -97,38 +495,633 @@ pub fn get_config_dir() -> Result<PathBuf> {
     Ok(path)
 }
 
-pub fn load_config() -> Config {
-    let Ok(mut path) = get_config_dir() else {
-        return Config::default();
+/// The default location of the config file, `config.toml` in [`get_config_dir`]
+pub fn get_config_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("config.toml"))
+}
+
+/// Loads the config file at `path`, falling back to the defaults if it is missing.
+/// A broken config file also falls back to the defaults unless `strict` is set.
+pub fn load_config(path: &Path, strict: bool) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    match read_config(path, strict) {
+        Ok(config) => Ok(config),
+        Err(e) if strict => Err(e),
+        Err(e) => {
+            eprintln!("Error loading config: {:#}. Using defaults.", e);
+            Ok(Config::default())
+        }
+    }
+}
+
+/// Reads the config file at `path`. Problems found by [`Config::problems`] are
+/// printed as warnings, or are an error if `strict` is set.
+pub fn read_config(path: &Path, strict: bool) -> Result<Config> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let config = parse_config(&content).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+
+    let problems = config.problems();
+    if strict && !problems.is_empty() {
+        bail!("{}: {}", path.display(), problems.join(", "));
+    }
+    for problem in problems {
+        eprintln!("Warning: {}: {}", path.display(), problem);
+    }
+    Ok(config)
+}
+
+/// Parses a config file, errors point at the line and column of the offending value
+pub fn parse_config(content: &str) -> Result<Config, String> {
+    toml::from_str(content).map_err(|e| match e.span() {
+        Some(span) => {
+            let (line, column) = line_column(content, span.start);
+            format!("line {}, column {}: {}", line, column, e.message())
+        }
+        None => e.message().to_string(),
+    })
+}
+
+/// 1-based line and column of the byte `offset` in `content`
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Checks the config file at `path`, returning every problem found in it
+pub fn check_config(path: &Path) -> Result<Vec<String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(match parse_config(&content) {
+        Ok(config) => config.problems(),
+        Err(e) => vec![e],
+    })
+}
+
+impl GeometryOverride {
+    fn check(&self, problems: &mut Vec<String>, prefix: &str) {
+        check_positive(problems, &format!("{}.geometry.width", prefix), self.width);
+        check_positive(
+            problems,
+            &format!("{}.geometry.height", prefix),
+            self.height,
+        );
+        check_not_negative(problems, &format!("{}.geometry.gap", prefix), self.gap);
+        check_positive(
+            problems,
+            &format!("{}.geometry.columns", prefix),
+            self.columns.map(|c| (c as i32).into()),
+        );
+        check_positive(
+            problems,
+            &format!("{}.geometry.collapsed", prefix),
+            self.collapsed,
+        );
+    }
+}
+
+impl MarginsOverride {
+    fn check(&self, problems: &mut Vec<String>, prefix: &str) {
+        for (side, margin) in [
+            ("top", self.top),
+            ("right", self.right),
+            ("left", self.left),
+            ("bottom", self.bottom),
+        ] {
+            check_not_negative(problems, &format!("{}.margins.{}", prefix, side), margin);
+        }
+    }
+}
+
+fn check_positive(problems: &mut Vec<String>, name: &str, size: Option<Size>) {
+    if let Some(size) = size
+        && !size.is_positive()
+    {
+        problems.push(format!("{} must be greater than 0, got {}", name, size));
+    }
+}
+
+fn check_not_negative(problems: &mut Vec<String>, name: &str, size: Option<Size>) {
+    if let Some(size) = size
+        && size.is_negative()
+    {
+        problems.push(format!("{} must not be negative, got {}", name, size));
+    }
+}
+
+/// Peeking further than the window is wide leaves a gap between the window and the screen edge
+fn check_peek(
+    problems: &mut Vec<String>,
+    name: &str,
+    peek: Size,
+    position: SidebarPosition,
+    (width, height): (Size, Size),
+) {
+    let (across, dimension) = match position {
+        SidebarPosition::Left | SidebarPosition::Right => (width, "width"),
+        SidebarPosition::Top | SidebarPosition::Bottom => (height, "height"),
     };
-    path.push("config.toml");
+    if peek.exceeds(across) {
+        problems.push(format!(
+            "{} ({}) is bigger than the window {} ({})",
+            name, peek, dimension, across
+        ));
+    }
+}
+
+impl Config {
+    /// Values that parse fine but cannot give a working sidebar
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
 
-    if path.exists() {
-        if let Ok(content) = fs::read_to_string(&path) {
-            match toml::from_str(&content) {
-                Ok(cfg) => return cfg,
-                Err(e) => eprintln!("Error parsing config.toml: {}. Using defaults.", e),
+        let geometry = &self.geometry;
+        check_positive(&mut problems, "geometry.width", Some(geometry.width));
+        check_positive(&mut problems, "geometry.height", Some(geometry.height));
+        check_not_negative(&mut problems, "geometry.gap", Some(geometry.gap));
+        check_positive(
+            &mut problems,
+            "geometry.columns",
+            Some((geometry.columns as i32).into()),
+        );
+        check_positive(
+            &mut problems,
+            "geometry.collapsed",
+            Some(geometry.collapsed),
+        );
+        for (name, margin) in [
+            ("top", self.margins.top),
+            ("right", self.margins.right),
+            ("left", self.margins.left),
+            ("bottom", self.margins.bottom),
+        ] {
+            check_not_negative(&mut problems, &format!("margins.{}", name), Some(margin));
+        }
+        check_not_negative(
+            &mut problems,
+            "interaction.peek",
+            Some(self.interaction.peek),
+        );
+        check_not_negative(
+            &mut problems,
+            "interaction.focus_peek",
+            self.interaction.focus_peek,
+        );
+        let sizes = (geometry.width, geometry.height);
+        check_peek(
+            &mut problems,
+            "interaction.peek",
+            self.interaction.peek,
+            self.interaction.position,
+            sizes,
+        );
+
+        for (name, output) in &self.output {
+            let prefix = format!("output.\"{}\"", name);
+            output.geometry.check(&mut problems, &prefix);
+            output.margins.check(&mut problems, &prefix);
+
+            // The global peek was already checked against the global sizes
+            let overrides = &output.geometry;
+            if overrides.width.is_some() || overrides.height.is_some() || output.position.is_some()
+            {
+                let layout = self.layout_for(None, Some(name));
+                check_peek(
+                    &mut problems,
+                    &format!("interaction.peek on {}", prefix),
+                    layout.interaction.peek,
+                    layout.interaction.position,
+                    (layout.geometry.width, layout.geometry.height),
+                );
+            }
+        }
+
+        for (name, profile) in &self.profile {
+            let prefix = format!("profile.{}", name);
+            profile.geometry.check(&mut problems, &prefix);
+            profile.margins.check(&mut problems, &prefix);
+            let interaction = &profile.interaction;
+            check_not_negative(
+                &mut problems,
+                &format!("{}.interaction.peek", prefix),
+                interaction.peek,
+            );
+            check_not_negative(
+                &mut problems,
+                &format!("{}.interaction.focus_peek", prefix),
+                interaction.focus_peek,
+            );
+
+            let geometry = &profile.geometry;
+            if geometry.width.is_some()
+                || geometry.height.is_some()
+                || interaction.position.is_some()
+                || interaction.peek.is_some()
+            {
+                let layout = self.layout_for(Some(name), None);
+                check_peek(
+                    &mut problems,
+                    &format!("interaction.peek in {}", prefix),
+                    layout.interaction.peek,
+                    layout.interaction.position,
+                    (layout.geometry.width, layout.geometry.height),
+                );
             }
         }
+
+        for (index, rule) in self.window_rule.iter().enumerate() {
+            let prefix = format!("window_rule {}", index + 1);
+            check_positive(&mut problems, &format!("{} width", prefix), rule.width);
+            check_positive(&mut problems, &format!("{} height", prefix), rule.height);
+            check_not_negative(&mut problems, &format!("{} peek", prefix), rule.peek);
+            check_not_negative(
+                &mut problems,
+                &format!("{} focus_peek", prefix),
+                rule.focus_peek,
+            );
+            let sets_across = match self.interaction.position {
+                SidebarPosition::Left | SidebarPosition::Right => rule.width.is_some(),
+                SidebarPosition::Top | SidebarPosition::Bottom => rule.height.is_some(),
+            };
+            if rule.peek.is_some() || sets_across {
+                check_peek(
+                    &mut problems,
+                    &format!("{} peek", prefix),
+                    rule.peek.unwrap_or(self.interaction.peek),
+                    self.interaction.position,
+                    (
+                        rule.width.unwrap_or(geometry.width),
+                        rule.height.unwrap_or(geometry.height),
+                    ),
+                );
+            }
+        }
+
+        problems
     }
-    Config::default()
 }
 
-pub fn init_config() -> Result<()> {
-    let mut path = get_config_dir()?;
+/// Writes the default config to `path`, replacing an existing file only if `force` is set
+pub fn init_config(path: &Path, force: bool) -> Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir)?;
+        println!("Created directory: {:?}", dir);
+    }
 
-    if !path.exists() {
-        fs::create_dir_all(&path)?;
-        println!("Created directory: {:?}", path);
+    if path.exists() && !force {
+        anyhow::bail!(
+            "Config file already exists at {:?}, use --merge to add missing settings or --force to replace it",
+            path
+        );
     }
 
-    path.push("config.toml");
+    fs::write(path, DEFAULT_CONFIG_STR)?;
+    println!("Default config written to {:?}", path);
+    Ok(())
+}
 
-    if path.exists() {
-        anyhow::bail!("Config file already exists at {:?}", path);
+/// Adds the settings and examples of the default config that are missing from the
+/// config file at `path`, keeping the values and comments already in it
+pub fn merge_config_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return init_config(path, false);
     }
 
-    fs::write(&path, DEFAULT_CONFIG_STR)?;
-    println!("Default config written to {:?}", path);
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (merged, added) =
+        merge_config(&content).with_context(|| format!("Failed to merge {}", path.display()))?;
+    if added.is_empty() {
+        println!("{:?} already has every setting", path);
+        return Ok(());
+    }
+
+    fs::write(path, merged)?;
+    for item in &added {
+        println!("Added {}", item);
+    }
+    println!("Updated {:?}", path);
     Ok(())
 }
+
+/// Merges the default config into `content`, returns the new file and what was added to it
+pub fn merge_config(content: &str) -> Result<(String, Vec<String>)> {
+    let mut doc: DocumentMut = content.parse()?;
+    let defaults: DocumentMut = DEFAULT_CONFIG_STR
+        .parse()
+        .expect("Default config file is invalid TOML");
+    let mut added = Vec::new();
+
+    // New tables go after the ones already in the file
+    let mut next_position = doc
+        .iter()
+        .filter_map(|(_, item)| item.as_table()?.position())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    merge_table(
+        doc.as_table_mut(),
+        defaults.as_table(),
+        "",
+        &mut next_position,
+        &mut added,
+    );
+
+    // Commented out examples at the end of the default config
+    let mut trailing = doc.trailing().as_str().unwrap_or_default().to_string();
+    let existing: HashSet<&str> = content.lines().map(str::trim).collect();
+    let examples = defaults.trailing().as_str().unwrap_or_default();
+    for paragraph in examples.split("\n\n").map(str::trim) {
+        let Some(first_line) = paragraph.lines().next() else {
+            continue;
+        };
+        if !paragraph.lines().all(|line| line.starts_with('#')) || existing.contains(first_line) {
+            continue;
+        }
+        let separator = if trailing.trim().is_empty() { "" } else { "\n" };
+        trailing = format!("{}{}\n{}\n", trailing.trim_end(), separator, paragraph);
+        added.push(format!("comment \"{}\"", first_line));
+    }
+    doc.set_trailing(trailing);
+
+    Ok((doc.to_string(), added))
+}
+
+fn merge_table(
+    table: &mut Table,
+    defaults: &Table,
+    path: &str,
+    next_position: &mut isize,
+    added: &mut Vec<String>,
+) {
+    for (key, default) in defaults.iter() {
+        let name = if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        };
+
+        if let Some(item) = table.get_mut(key) {
+            if let (Some(table), Some(defaults)) = (item.as_table_mut(), default.as_table()) {
+                merge_table(table, defaults, &name, next_position, added);
+            }
+            continue;
+        }
+
+        let mut item = default.clone();
+        if let Some(table) = item.as_table_mut() {
+            table.set_position(*next_position);
+            *next_position += 1;
+        }
+        // The key carries the comments in front of it
+        let key = defaults.key(key).expect("Key of an existing entry");
+        table.insert_formatted(key, item);
+        added.push(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sizes() {
+        let geometry: GeometryOverride = toml::from_str(
+            r#"
+            width = "25%"
+            height = 0.3
+            gap = "1%"
+            collapsed = "5%"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(geometry.width, Some(Size::Fraction(0.25)));
+        assert_eq!(geometry.height, Some(Size::Fraction(0.3)));
+        assert_eq!(geometry.gap, Some(Size::Fraction(0.01)));
+        assert_eq!(geometry.collapsed, Some(Size::Fraction(0.05)));
+        assert_eq!(Size::Fraction(0.07).to_string(), "7%");
+        assert_eq!(Size::Fraction(0.125).to_string(), "12.5%");
+
+        let margins: MarginsOverride = toml::from_str(
+            r#"
+            top = 50
+            left = "0.1"
+            right = "20"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(margins.top, Some(Size::Pixels(50)));
+        assert_eq!(margins.left, Some(Size::Fraction(0.1)));
+        assert_eq!(margins.right, Some(Size::Pixels(20)));
+
+        assert!(toml::from_str::<GeometryOverride>(r#"width = "wide""#).is_err());
+    }
+
+    #[test]
+    fn test_parse_insert() {
+        let rules: Config = toml::from_str(&format!(
+            "{}\n[[window_rule]]\ninsert = \"top\"\n[[window_rule]]\ninsert = 3\n",
+            DEFAULT_CONFIG_STR
+        ))
+        .unwrap();
+        assert_eq!(rules.interaction.insert, Insert::Top);
+        assert_eq!(rules.window_rule[0].insert, Some(Insert::Top));
+        assert_eq!(rules.window_rule[1].insert, Some(Insert::Index(3)));
+
+        for invalid in ["insert = 0", "insert = \"middle\""] {
+            let config = format!("{}\n[[window_rule]]\n{}\n", DEFAULT_CONFIG_STR, invalid);
+            assert!(toml::from_str::<Config>(&config).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(Insert::Top.index(3), 3);
+        assert_eq!(Insert::Bottom.index(3), 0);
+        assert_eq!(Insert::Index(2).index(3), 1);
+        assert_eq!(Insert::Index(9).index(3), 3);
+    }
+
+    #[test]
+    fn test_resolve_sizes_against_output() {
+        assert_eq!(Size::Pixels(300).resolve(1366), 300);
+        assert_eq!(Size::Fraction(0.25).resolve(1366), 342);
+        assert_eq!(Size::Fraction(0.25).resolve(2560), 640);
+
+        let margins = Margins {
+            top: Size::Fraction(0.1),
+            right: 20.into(),
+            left: Size::Fraction(0.5),
+            bottom: 0.into(),
+        };
+        assert_eq!(
+            margins.resolve((1920, 1080)),
+            PixelMargins {
+                top: 108,
+                right: 20,
+                left: 960,
+                bottom: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_config_reports_line_and_column() {
+        let content = format!(
+            "{}\n[[window_rule]]\napp_id = \"(unclosed\"\n",
+            DEFAULT_CONFIG_STR
+        );
+        // Matchers are flattened into the rule, so errors in them point at the rule itself
+        let line = content.lines().count() - 1;
+        let error = parse_config(&content).unwrap_err();
+        assert!(
+            error.starts_with(&format!("line {}, column 1: regex parse error", line)),
+            "{}",
+            error
+        );
+
+        let error = parse_config("[geometry]\nwidth = \"wide\"\n").unwrap_err();
+        assert!(
+            error.starts_with("line 2, column 9: invalid size \"wide\""),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_config_problems() {
+        assert!(Config::default().problems().is_empty());
+
+        let mut config = Config::default();
+        config.geometry.width = 0.into();
+        config.geometry.gap = (-5).into();
+        config.interaction.peek = 20.into();
+        config.output.insert(
+            "DP-1".into(),
+            OutputConfig {
+                geometry: GeometryOverride {
+                    width: Some(10.into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        config.window_rule.push(WindowRule {
+            height: Some(Size::Fraction(-0.5)),
+            ..Default::default()
+        });
+        config.window_rule.push(WindowRule {
+            width: Some(Size::Fraction(0.2)),
+            peek: Some(Size::Fraction(0.3)),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            config.problems(),
+            vec![
+                "geometry.width must be greater than 0, got 0",
+                "geometry.gap must not be negative, got -5",
+                "interaction.peek (20) is bigger than the window width (0)",
+                "interaction.peek on output.\"DP-1\" (20) is bigger than the window width (10)",
+                "window_rule 1 height must be greater than 0, got -50%",
+                "window_rule 2 peek (30%) is bigger than the window width (20%)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config: Config = toml::from_str(&format!(
+            "{}\n[profile.coding]\ninteraction = {{ position = \"left\", peek = -1 }}\n\n[profile.coding.geometry]\nwidth = 600\n",
+            DEFAULT_CONFIG_STR
+        ))
+        .unwrap();
+
+        let layout = config.layout_for(Some("coding"), None);
+        assert_eq!(layout.interaction.position, SidebarPosition::Left);
+        assert_eq!(layout.geometry.width, 600.into());
+        assert_eq!(layout.geometry.height, 335.into());
+        assert_eq!(
+            config.layout_for(Some("missing"), None).geometry.width,
+            400.into()
+        );
+
+        assert_eq!(
+            config.problems(),
+            vec!["profile.coding.interaction.peek must not be negative, got -1"]
+        );
+    }
+
+    #[test]
+    fn test_check_config_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        fs::write(&path, DEFAULT_CONFIG_STR).unwrap();
+        assert!(check_config(&path).unwrap().is_empty());
+
+        fs::write(&path, "[geometry\n").unwrap();
+        let problems = check_config(&path).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("line 1, column 10: "),
+            "{}",
+            problems[0]
+        );
+
+        assert!(check_config(&temp_dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn test_init_config_at_custom_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("dotfiles/niri-sidebar.toml");
+
+        init_config(&path, false).expect("Init failed");
+        assert_eq!(fs::read_to_string(&path).unwrap(), DEFAULT_CONFIG_STR);
+        assert!(init_config(&path, false).is_err());
+        fs::write(&path, "").unwrap();
+        init_config(&path, true).expect("Forced init failed");
+        assert_eq!(fs::read_to_string(&path).unwrap(), DEFAULT_CONFIG_STR);
+    }
+
+    #[test]
+    fn test_merge_config_keeps_user_settings() {
+        let content = "# my sidebar\n[geometry]\nwidth = 500 # wider!\nheight = 335\ngap = 10\n\n[margins]\ntop = 50\n";
+
+        let (merged, added) = merge_config(content).unwrap();
+
+        assert!(merged.starts_with("# my sidebar\n[geometry]\nwidth = 500 # wider!\n"));
+        assert!(merged.contains("# Number of lines used by the \"grid\" layout\ncolumns = 2\n"));
+        assert!(merged.contains("\n\n# Example window rule\n"));
+        assert!(!merged.contains("\n\n\n"));
+        assert_eq!(
+            &added[..5],
+            [
+                "geometry.layout",
+                "geometry.columns",
+                "geometry.accordion",
+                "geometry.collapsed",
+                "margins.right",
+            ]
+        );
+        assert!(added.contains(&"interaction".to_string()));
+        assert!(added.contains(&"comment \"# Example profile\"".to_string()));
+
+        let config = parse_config(&merged).unwrap();
+        assert_eq!(config.geometry.width, 500.into());
+        assert_eq!(config.interaction.peek, 10.into());
+
+        // Merging again, or merging into the default config, changes nothing
+        assert_eq!(merge_config(&merged).unwrap(), (merged, vec![]));
+        let (default, added) = merge_config(DEFAULT_CONFIG_STR).unwrap();
+        assert_eq!(default, DEFAULT_CONFIG_STR);
+        assert!(added.is_empty());
+
+        assert!(merge_config("[geometry").is_err());
+    }
+}
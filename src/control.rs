@@ -0,0 +1,334 @@
+use crate::commands::{self, FocusTarget, Move};
+use crate::config::Insert;
+use crate::{Ctx, Direction, NiriClient};
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use clap::builder::RangedU64ValueParser;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// Commands that operate on the sidebar, either handled by the `listen` daemon
+/// or executed directly when no daemon is running
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Toggle the focused window in/out of the sidebar
+    ToggleWindow,
+    /// Hide or show the sidebar
+    ToggleVisibility,
+    /// Reverse the order of windows in the stack
+    Flip,
+    /// Force re-stacking of windows
+    Reorder,
+    /// Close the focused window and reorder the sidebar
+    Close,
+    /// Focus and cycle through the windows in the sidebar
+    Focus {
+        #[arg(value_enum, default_value_t = Direction::Next)]
+        direction: Direction,
+        /// Focus the window in this slot instead, counted from 1 at the bottom
+        #[arg(
+            long,
+            conflicts_with_all = ["direction", "last"],
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        index: Option<usize>,
+        /// Jump back to the most recently focused sidebar window instead
+        #[arg(long, conflicts_with = "direction")]
+        last: bool,
+    },
+    /// Jump between the sidebar and the window outside it that was focused last
+    FocusToggle,
+    /// Focus the sidebar window of an app and reveal the sidebar, or launch the app if it has none
+    Summon {
+        /// App id of the window, as shown by `niri msg windows`
+        #[arg(long)]
+        app_id: String,
+        /// Shell command that opens the window, it is added to the sidebar if it appears
+        /// within 30 seconds. Needs the `listen` daemon
+        #[arg(long)]
+        exec: Option<String>,
+    },
+    /// Move the sidebar from a specific workspace to the current workspace
+    MoveFrom {
+        #[arg()]
+        workspace: u64,
+    },
+    /// Move the focused window one place towards the top of the stack
+    MoveUp,
+    /// Move the focused window one place towards the bottom of the stack
+    MoveDown,
+    /// Move the focused window to the top of the stack
+    MoveToTop,
+    /// Move the focused window to the bottom of the stack
+    MoveToBottom,
+    /// Move the focused window to a slot of the stack, counted from 1 at the bottom
+    MoveTo {
+        #[arg(value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        index: usize,
+    },
+    /// Swap the focused tiled window with a sidebar window
+    Swap {
+        /// Slot of the sidebar window, counted from 1 at the bottom.
+        /// Defaults to the most recently focused sidebar window
+        #[arg(value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        index: Option<usize>,
+    },
+    /// Print the windows currently held by the sidebars
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Switch to a config profile, or list the profiles if no name is given
+    Profile {
+        /// Name of a `[profile.<name>]` table in the config
+        #[arg()]
+        name: Option<String>,
+        /// Go back to the settings without any profile
+        #[arg(long, conflicts_with = "name")]
+        reset: bool,
+    },
+    /// Inspect the window rules
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommand,
+    },
+}
+
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RulesCommand {
+    /// Explain which window rules apply to a window and what they resolve to
+    Test {
+        /// Id of the window, as shown by `niri msg windows`. Defaults to the focused window
+        #[arg()]
+        window: Option<u64>,
+        /// Print the explanation as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Everything a client can send over the control socket
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    Request(Request),
+    /// Keep the connection open and receive a status line whenever the sidebar changes
+    Watch,
+}
+
+/// Output to print on success, or the error message on failure
+pub type Reply = Result<Option<String>, String>;
+
+pub fn socket_path(cache_dir: &Path) -> PathBuf {
+    let mut path = cache_dir.to_path_buf();
+    path.push("control.sock");
+    path
+}
+
+pub fn dispatch<C: NiriClient>(ctx: &mut Ctx<C>, request: Request) -> Result<Option<String>> {
+    match request {
+        Request::ToggleWindow => commands::toggle_window(ctx)?,
+        Request::ToggleVisibility => commands::toggle_visibility(ctx)?,
+        Request::Flip => commands::toggle_flip(ctx)?,
+        Request::Reorder => commands::reorder(ctx)?,
+        Request::Close => commands::close(ctx)?,
+        Request::Focus {
+            index: Some(index), ..
+        } => commands::focus(ctx, FocusTarget::Index(index))?,
+        Request::Focus { last: true, .. } => commands::focus(ctx, FocusTarget::Last)?,
+        Request::Focus { direction, .. } => commands::focus(ctx, FocusTarget::Cycle(direction))?,
+        Request::FocusToggle => commands::focus_toggle(ctx)?,
+        Request::Summon { app_id, exec } => commands::summon(ctx, &app_id, exec.as_deref())?,
+        Request::MoveFrom { workspace } => commands::move_from(ctx, workspace)?,
+        Request::MoveUp => commands::move_window(ctx, Move::Up)?,
+        Request::MoveDown => commands::move_window(ctx, Move::Down)?,
+        Request::MoveToTop => commands::move_window(ctx, Move::To(Insert::Top))?,
+        Request::MoveToBottom => commands::move_window(ctx, Move::To(Insert::Bottom))?,
+        Request::MoveTo { index } => commands::move_window(ctx, Move::To(Insert::Index(index)))?,
+        Request::Swap { index } => commands::swap(ctx, index)?,
+        Request::Status { json } => return commands::status(ctx, json).map(Some),
+        Request::Profile {
+            name: None,
+            reset: false,
+        } => {
+            return Ok(Some(commands::list_profiles(ctx)));
+        }
+        Request::Profile { name, .. } => commands::set_profile(ctx, name)?,
+        Request::Rules {
+            command: RulesCommand::Test { window, json },
+        } => return commands::rules_test(ctx, window, json).map(Some),
+    }
+
+    Ok(None)
+}
+
+/// Sends `request` to the daemon listening on `path`.
+/// Returns `None` if no daemon is running.
+pub fn send(path: &Path, request: &Request) -> Result<Option<Reply>> {
+    let Some(stream) = connect(path)? else {
+        return Ok(None);
+    };
+
+    write_line(&stream, &ClientMessage::Request(request.clone()))?;
+    stream.shutdown(Shutdown::Write)?;
+    let reply = read_line(&stream).context("Invalid reply from the niri-sidebar daemon")?;
+    Ok(Some(reply))
+}
+
+/// Subscribes to the daemon listening on `path` and copies every status line to `out`
+/// until the daemon goes away
+pub fn watch(path: &Path, mut out: impl Write) -> Result<()> {
+    let stream = connect(path)?
+        .context("No niri-sidebar daemon is running, start one with `niri-sidebar listen`")?;
+
+    write_line(&stream, &ClientMessage::Watch)?;
+    for line in BufReader::new(&stream).lines() {
+        writeln!(out, "{}", line?)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+fn connect(path: &Path) -> Result<Option<UnixStream>> {
+    match UnixStream::connect(path) {
+        Ok(stream) => Ok(Some(stream)),
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            Ok(None)
+        }
+        Err(e) => Err(e).context("Failed to connect to the niri-sidebar daemon"),
+    }
+}
+
+pub fn read_message(stream: &UnixStream) -> Result<ClientMessage> {
+    read_line(stream).context("Invalid message sent to the niri-sidebar daemon")
+}
+
+pub fn write_reply(stream: &UnixStream, reply: &Reply) -> Result<()> {
+    write_line(stream, reply)
+}
+
+fn write_line<T: Serialize>(mut stream: &UnixStream, message: &T) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn read_line<T: for<'de> Deserialize<'de>>(stream: &UnixStream) -> Result<T> {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        bail!("Connection closed before a message was received");
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{AppState, WindowState};
+    use crate::test_utils::{MockNiri, mock_config, mock_window, mock_window_state};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_send_without_daemon_returns_none() {
+        let temp_dir = tempdir().unwrap();
+        let path = socket_path(temp_dir.path());
+
+        let reply = send(&path, &Request::Reorder).expect("Send failed");
+        assert!(reply.is_none());
+    }
+
+    #[test]
+    fn test_request_reply_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let path = socket_path(temp_dir.path());
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let message = read_message(&stream).unwrap();
+            let reply: Reply = Ok(Some(format!("{:?}", message)));
+            write_reply(&stream, &reply).unwrap();
+        });
+
+        let request = Request::Focus {
+            direction: Direction::Prev,
+            index: None,
+            last: false,
+        };
+        let reply = send(&path, &request).expect("Send failed");
+        server.join().unwrap();
+
+        assert_eq!(
+            reply,
+            Some(Ok(Some(
+                "Request(Focus { direction: Prev, index: None, last: false })".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_watch_copies_status_lines() {
+        let temp_dir = tempdir().unwrap();
+        let path = socket_path(temp_dir.path());
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            assert_eq!(read_message(&stream).unwrap(), ClientMessage::Watch);
+            stream.write_all(b"{\"count\":1}\n{\"count\":2}\n").unwrap();
+        });
+
+        let mut out = Vec::new();
+        watch(&path, &mut out).expect("Watch failed");
+        server.join().unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"count\":1}\n{\"count\":2}\n"
+        );
+    }
+
+    #[test]
+    fn test_watch_without_daemon_fails() {
+        let temp_dir = tempdir().unwrap();
+        let path = socket_path(temp_dir.path());
+
+        assert!(watch(&path, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_dispatch_runs_command() {
+        let temp_dir = tempdir().unwrap();
+        let win = mock_window(100, true, true, 1, Some((1.0, 2.0)));
+        let mock = MockNiri::new(vec![win]);
+
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 300,
+            height: 200,
+            is_floating: true,
+            ..mock_window_state(100)
+        });
+
+        let mut ctx = Ctx {
+            state,
+            config: mock_config(),
+            socket: mock,
+            cache_dir: temp_dir.path().to_path_buf(),
+        };
+
+        let output = dispatch(&mut ctx, Request::ToggleVisibility).expect("Dispatch failed");
+
+        assert!(output.is_none());
+        assert!(ctx.state.sidebar(1).unwrap().is_hidden);
+        assert!(!ctx.socket.sent_actions.is_empty());
+    }
+}
@@ -1,14 +1,18 @@
 pub mod commands;
 pub mod config;
+pub mod control;
 pub mod niri;
 pub mod state;
 pub mod window_rules;
 
 use std::path::PathBuf;
 
+use anyhow::Result;
 use clap::ValueEnum;
+use niri_ipc::socket::Socket;
+use serde::{Deserialize, Serialize};
 
-pub use crate::config::Config;
+pub use crate::config::{Config, OutputLayout};
 pub use crate::niri::NiriClient;
 pub use crate::state::AppState;
 
@@ -22,7 +26,34 @@ pub struct Ctx<C: NiriClient> {
     pub cache_dir: PathBuf,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+impl Ctx<Socket> {
+    /// Loads state from disk and connects to niri.
+    /// Callers should hold the instance lock while the state is read.
+    pub fn load(cache_dir: PathBuf, config: Config) -> Result<Self> {
+        let mut ctx = Ctx {
+            state: state::load_state(&cache_dir)?,
+            config,
+            socket: niri::connect()?,
+            cache_dir,
+        };
+        if ctx.state.legacy_sidebar.is_some() {
+            let workspace = ctx.socket.get_active_workspace()?.id;
+            ctx.state.migrate_legacy(workspace);
+            state::save_state(&ctx.state, &ctx.cache_dir)?;
+        }
+        Ok(ctx)
+    }
+}
+
+impl<C: NiriClient> Ctx<C> {
+    /// The settings for `output` with the active profile applied
+    pub fn layout_for(&self, output: Option<&str>) -> OutputLayout {
+        self.config
+            .layout_for(self.state.profile.as_deref(), output)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Serialize, Deserialize)]
 pub enum Direction {
     Next,
     Prev,
@@ -1,91 +1,125 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use clap::{Parser, Subcommand};
-use fslock::LockFile;
-use niri_sidebar::config::load_config;
-use niri_sidebar::state::{get_default_cache_dir, load_state};
-use niri_sidebar::{AppState, Ctx, config, niri::connect};
-use niri_sidebar::{Direction, commands};
+use niri_sidebar::control::{self, Request};
+use niri_sidebar::state::{get_state_dir, lock_instance};
+use niri_sidebar::{Ctx, commands, config};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "niri-sidebar")]
 #[command(about = "A floating sidebar manager for Niri")]
 struct Cli {
+    /// Config file to use instead of ~/.config/niri-sidebar/config.toml
+    #[arg(long, global = true, env = "NIRI_SIDEBAR_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Directory for the state file and control socket instead of ~/.cache/niri-sidebar
+    #[arg(
+        long,
+        global = true,
+        env = "NIRI_SIDEBAR_STATE_DIR",
+        value_name = "DIR"
+    )]
+    state_dir: Option<PathBuf>,
+    /// Fail instead of falling back to the defaults when the config file has problems
+    #[arg(long, global = true)]
+    strict: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Toggle the focused window in/out of the sidebar
-    ToggleWindow,
-    /// Hide or show the sidebar
-    ToggleVisibility,
-    /// Reverse the order of windows in the stack
-    Flip,
-    /// Force re-stacking of windows
-    Reorder,
-    /// Close the focused window and reorder the sidebar
-    Close,
-    /// Focus and cycle through the windows in the sidebar
-    Focus {
-        #[arg(value_enum, default_value_t = Direction::Next)]
-        direction: Direction,
-    },
-    /// Move the sidebar from a specific workspace to the current workspace
-    MoveFrom {
-        #[arg()]
-        workspace: u64,
-    },
+    #[command(flatten)]
+    Request(Request),
     /// Generate a default config file if none exists
-    Init,
-    /// Run a daemon to listen for window close events
+    Init {
+        /// Replace an existing config file with the defaults
+        #[arg(long, conflicts_with_all = ["merge", "print"])]
+        force: bool,
+        /// Add the settings missing from an existing config file, keeping its values and comments
+        #[arg(long, conflicts_with = "print")]
+        merge: bool,
+        /// Write the default config to stdout instead of the config file
+        #[arg(long)]
+        print: bool,
+    },
+    /// Check the config file for errors
+    CheckConfig,
+    /// Run a daemon that tracks window events and serves the other commands
     Listen,
+    /// Print a JSON line for waybar custom modules whenever the sidebar changes
+    Watch,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Init doesn't require locks or state loading
-    if let Commands::Init = cli.command {
-        return config::init_config();
-    }
-
-    let cache_dir = get_default_cache_dir()?;
-    let mut lock_path = cache_dir.clone();
-    lock_path.push("instance.lock");
-    let mut lock_file = LockFile::open(&lock_path)?;
-
-    // Listener will handle its own locking when it needs to write
-    if !matches!(cli.command, Commands::Listen) && !lock_file.try_lock()? {
-        lock_file.lock()?;
-    }
-    let config = load_config();
-    // Listener will load state on demand
-    let state = if matches!(cli.command, Commands::Listen) {
-        AppState::default()
-    } else {
-        load_state(&cache_dir)?
+    let config_path = match cli.config {
+        Some(path) => path,
+        None => config::get_config_path()?,
     };
-    let socket = connect()?;
 
-    let mut ctx = Ctx {
-        state,
-        config,
-        socket,
-        cache_dir,
+    let request = match cli.command {
+        // Init doesn't require locks or state loading
+        Commands::Init { print: true, .. } => {
+            print!("{}", config::DEFAULT_CONFIG_STR);
+            return Ok(());
+        }
+        Commands::Init { merge: true, .. } => return config::merge_config_file(&config_path),
+        Commands::Init { force, .. } => return config::init_config(&config_path, force),
+        Commands::CheckConfig => return check_config(&config_path),
+        Commands::Listen => {
+            let config = config::load_config(&config_path, cli.strict)?;
+            let cache_dir = get_state_dir(cli.state_dir)?;
+            let ctx = {
+                let _lock = lock_instance(&cache_dir)?;
+                Ctx::load(cache_dir, config)?
+            };
+            return commands::listen(ctx, config_path, cli.strict);
+        }
+        Commands::Watch => {
+            let cache_dir = get_state_dir(cli.state_dir)?;
+            return control::watch(&control::socket_path(&cache_dir), std::io::stdout());
+        }
+        Commands::Request(request) => request,
     };
 
-    match cli.command {
-        Commands::ToggleWindow => commands::toggle_window(&mut ctx)?,
-        Commands::ToggleVisibility => commands::toggle_visibility(&mut ctx)?,
-        Commands::Flip => commands::toggle_flip(&mut ctx)?,
-        Commands::Reorder => commands::reorder(&mut ctx)?,
-        Commands::Close => commands::close(&mut ctx)?,
-        Commands::Focus { direction } => commands::focus(&mut ctx, direction)?,
-        Commands::MoveFrom { workspace } => commands::move_from(&mut ctx, workspace)?,
-        Commands::Init => unreachable!(),
-        Commands::Listen => commands::listen(ctx)?,
+    let cache_dir = get_state_dir(cli.state_dir)?;
+
+    // Let the daemon handle the request if one is running
+    if let Some(reply) = control::send(&control::socket_path(&cache_dir), &request)? {
+        if let Some(output) = reply.map_err(anyhow::Error::msg)? {
+            println!("{}", output);
+        }
+        return Ok(());
+    }
+
+    let config = config::load_config(&config_path, cli.strict)?;
+    let _lock = lock_instance(&cache_dir)?;
+    let mut ctx = Ctx::load(cache_dir, config)?;
+    if let Some(output) = control::dispatch(&mut ctx, request)? {
+        println!("{}", output);
     }
 
     Ok(())
 }
+
+fn check_config(path: &Path) -> Result<()> {
+    if !path.exists() {
+        println!(
+            "No config file at {}, the defaults are used",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let problems = config::check_config(path)?;
+    if problems.is_empty() {
+        println!("{} is valid", path.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}: {}", path.display(), problem);
+    }
+    bail!("Found {} problem(s) in {}", problems.len(), path.display())
+}
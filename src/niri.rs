@@ -6,9 +6,17 @@ pub use niri_ipc::{Window, Workspace};
 pub trait NiriClient {
     fn get_windows(&mut self) -> Result<Vec<Window>>;
     fn get_active_window(&mut self) -> Result<Window>;
-    fn get_active_workspace(&mut self) -> Result<Workspace>;
-    fn get_screen_dimensions(&mut self) -> Result<(i32, i32)>;
+    fn get_workspaces(&mut self) -> Result<Vec<Workspace>>;
+    /// Logical size of the output called `output`
+    fn get_output_dimensions(&mut self, output: &str) -> Result<(i32, i32)>;
     fn send_action(&mut self, action: Action) -> Result<Response>;
+
+    fn get_active_workspace(&mut self) -> Result<Workspace> {
+        self.get_workspaces()?
+            .into_iter()
+            .find(|w| w.is_focused)
+            .context("No active workspace found")
+    }
 }
 
 pub fn connect() -> Result<Socket> {
@@ -31,27 +39,19 @@ impl NiriClient for Socket {
         }
     }
 
-    fn get_active_workspace(&mut self) -> Result<Workspace> {
+    fn get_workspaces(&mut self) -> Result<Vec<Workspace>> {
         match self.send(Request::Workspaces)? {
-            Ok(Response::Workspaces(workspaces)) => workspaces
-                .into_iter()
-                .find(|w| w.is_focused)
-                .context("No active workspace found"),
+            Ok(Response::Workspaces(workspaces)) => Ok(workspaces),
             _ => bail!("Unexpected response from Niri when fetching workspaces"),
         }
     }
 
-    fn get_screen_dimensions(&mut self) -> Result<(i32, i32)> {
-        let workspace = self.get_active_workspace()?;
-        let target_output_name = workspace
-            .output
-            .context("Focused workspace is not on an output")?;
-
+    fn get_output_dimensions(&mut self, output: &str) -> Result<(i32, i32)> {
         match self.send(Request::Outputs)? {
             Ok(Response::Outputs(outputs)) => {
                 let output = outputs
                     .values()
-                    .find(|o| o.name == target_output_name)
+                    .find(|o| o.name == output)
                     .context("Output not found")?;
 
                 // Return the logical size
@@ -1,17 +1,96 @@
+use crate::config::Insert;
 use anyhow::{Context, Result};
+use fslock::LockFile;
+use niri_ipc::Window;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long `summon` waits for the window of a command it started
+pub const SUMMON_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long after the daemon starts the slots of windows that are not open are kept.
+/// niri hands out new ids after a restart, and after a login the apps may take a while to open.
+pub const RESTORE_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
 pub struct AppState {
-    pub windows: Vec<WindowState>,
+    /// Sidebars keyed by the id of the workspace they live on
+    #[serde(default)]
+    pub sidebars: BTreeMap<u64, SidebarState>,
     #[serde(default)]
     pub ignored_windows: Vec<u64>,
+    /// Windows pushed out of the sidebar for being tiled or fullscreen,
+    /// they are not added automatically again until they close
+    #[serde(default)]
+    pub ejected_windows: Vec<u64>,
+    /// Name of the active config profile
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Window outside the sidebars that had focus most recently, `focus-toggle` returns to it
+    #[serde(default)]
+    pub main_window: Option<u64>,
+    /// Windows launched by `summon` that are added to the sidebar when they open.
+    /// Only the daemon sees them open, so they are not kept across runs.
+    #[serde(skip)]
+    pub pending_summons: Vec<PendingSummon>,
+    /// Set while the `listen` daemon owns this state and reacts to window events
+    #[serde(skip)]
+    pub listening: bool,
+    /// When the daemon started, slots of windows that are not open are kept
+    /// for `RESTORE_TIMEOUT` after it so the windows can still be re-adopted
+    #[serde(skip)]
+    pub restore_started: Option<Instant>,
+    /// The sidebar of a state file written before sidebars were kept per workspace,
+    /// `Ctx::load` moves it to the focused workspace
+    #[serde(skip)]
+    pub legacy_sidebar: Option<SidebarState>,
+}
+
+/// State files of older versions had a single sidebar at the top level
+#[derive(Deserialize)]
+struct LegacyState {
+    windows: Vec<WindowState>,
+    #[serde(default)]
+    is_hidden: bool,
+    #[serde(default)]
+    is_flipped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSummon {
+    pub app_id: String,
+    pub started: Instant,
+}
+
+impl PendingSummon {
+    pub fn new(app_id: &str) -> Self {
+        PendingSummon {
+            app_id: app_id.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.started.elapsed() > SUMMON_TIMEOUT
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct SidebarState {
+    #[serde(default)]
+    pub windows: Vec<WindowState>,
     #[serde(default)]
     pub is_hidden: bool,
     #[serde(default)]
     pub is_flipped: bool,
+    /// Output the workspace of this sidebar was last seen on
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Sidebar windows in the order they last had focus, most recent first
+    #[serde(default)]
+    pub focus_history: Vec<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -21,23 +100,256 @@ pub struct WindowState {
     pub height: i32,
     pub is_floating: bool,
     pub position: Option<(f64, f64)>,
+    #[serde(flatten)]
+    pub identity: WindowIdentity,
+}
+
+/// What is known about a window besides its id, niri ids do not survive a compositor restart
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct WindowIdentity {
+    #[serde(default)]
+    pub app_id: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub pid: Option<i32>,
+}
+
+impl WindowIdentity {
+    pub fn of(window: &Window) -> Self {
+        WindowIdentity {
+            app_id: window.app_id.clone(),
+            title: window.title.clone(),
+            pid: window.pid,
+        }
+    }
+
+    /// How likely `window` is the window this identity was taken from, higher is better.
+    /// `None` if it can not be the same window, which takes the same app id and
+    /// at least the same title or pid.
+    pub fn match_score(&self, window: &Window) -> Option<u8> {
+        if self.app_id.is_none() || self.app_id != window.app_id {
+            return None;
+        }
+
+        let mut score = 0;
+        if self.title.is_some() && self.title == window.title {
+            score += 1;
+        }
+        if self.pid.is_some() && self.pid == window.pid {
+            score += 1;
+        }
+        (score > 0).then_some(score)
+    }
+}
+
+impl SidebarState {
+    /// The sidebar window that had focus most recently
+    pub fn last_focused(&self) -> Option<u64> {
+        self.focus_history.first().copied()
+    }
+
+    /// Position in `windows` of the window shown in `slot`, counted from 1 at the bottom.
+    /// Stacks grow from the start of the list unless the sidebar is flipped.
+    pub fn slot_index(&self, slot: usize) -> Option<usize> {
+        let index = slot.checked_sub(1).filter(|i| *i < self.windows.len())?;
+        Some(if self.is_flipped {
+            self.windows.len() - 1 - index
+        } else {
+            index
+        })
+    }
+
+    /// Position in `windows` at which a new window ends up shown at `insert`
+    pub fn insert_index(&self, insert: Insert) -> usize {
+        let index = insert.index(self.windows.len());
+        if self.is_flipped {
+            self.windows.len() - index
+        } else {
+            index
+        }
+    }
+
+    /// Moves `id` to the front of the focus history, returns whether the history changed
+    pub fn record_focus(&mut self, id: u64) -> bool {
+        if self.last_focused() == Some(id) {
+            return false;
+        }
+        self.focus_history.retain(|f| *f != id);
+        self.focus_history.insert(0, id);
+        true
+    }
+}
+
+impl AppState {
+    pub fn sidebar(&self, workspace: u64) -> Option<&SidebarState> {
+        self.sidebars.get(&workspace)
+    }
+
+    /// Returns the sidebar of `workspace`, creating an empty one if needed
+    pub fn sidebar_mut(&mut self, workspace: u64) -> &mut SidebarState {
+        self.sidebars.entry(workspace).or_default()
+    }
+
+    /// The windows in the sidebar of `workspace`, in stacking order
+    pub fn windows(&self, workspace: u64) -> &[WindowState] {
+        self.sidebar(workspace)
+            .map(|s| s.windows.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn all_windows(&self) -> impl Iterator<Item = &WindowState> {
+        self.sidebars.values().flat_map(|s| s.windows.iter())
+    }
+
+    /// Moves the sidebar of an older state file to `workspace`, returns whether there was one
+    pub fn migrate_legacy(&mut self, workspace: u64) -> bool {
+        let Some(legacy) = self.legacy_sidebar.take() else {
+            return false;
+        };
+        let windows: Vec<WindowState> = legacy
+            .windows
+            .into_iter()
+            .filter(|w| !self.is_tracked(w.id))
+            .collect();
+        let sidebar = self.sidebar_mut(workspace);
+        if sidebar.windows.is_empty() {
+            sidebar.is_hidden = legacy.is_hidden;
+            sidebar.is_flipped = legacy.is_flipped;
+        }
+        sidebar.windows.extend(windows);
+        true
+    }
+
+    pub fn is_tracked(&self, id: u64) -> bool {
+        self.all_windows().any(|w| w.id == id)
+    }
+
+    /// Returns the workspace and index of the sidebar slot holding window `id`
+    pub fn find_window(&self, id: u64) -> Option<(u64, usize)> {
+        self.sidebars.iter().find_map(|(ws, s)| {
+            s.windows
+                .iter()
+                .position(|w| w.id == id)
+                .map(|index| (*ws, index))
+        })
+    }
+
+    pub fn remove_window(&mut self, id: u64) -> Option<WindowState> {
+        let (ws, index) = self.find_window(id)?;
+        let sidebar = self.sidebars.get_mut(&ws)?;
+        sidebar.focus_history.retain(|f| *f != id);
+        Some(sidebar.windows.remove(index))
+    }
+
+    /// Inserts `window` into the sidebar of `workspace` at `index`, or at the end if it is past it
+    pub fn insert_window(&mut self, workspace: u64, index: usize, window: WindowState) {
+        let windows = &mut self.sidebar_mut(workspace).windows;
+        windows.insert(index.min(windows.len()), window);
+    }
+
+    /// Whether slots of windows that are not open are still kept for re-adoption
+    pub fn is_restoring(&self) -> bool {
+        self.restore_started
+            .is_some_and(|started| started.elapsed() < RESTORE_TIMEOUT)
+    }
+
+    /// Drops tracked windows that are not in `live_ids`, except for the ones that may
+    /// still be re-adopted. Returns whether any were removed.
+    pub fn prune_closed(&mut self, live_ids: &HashSet<u64>) -> bool {
+        let restoring = self.is_restoring();
+        self.retain_windows(|w| {
+            live_ids.contains(&w.id) || (restoring && w.identity.app_id.is_some())
+        })
+    }
+
+    /// Drops tracked windows for which `keep` returns false, returns whether any were removed
+    pub fn retain_windows(&mut self, mut keep: impl FnMut(&WindowState) -> bool) -> bool {
+        let mut changed = false;
+        for sidebar in self.sidebars.values_mut() {
+            let initial_len = sidebar.windows.len();
+            sidebar.windows.retain(&mut keep);
+            let windows = &sidebar.windows;
+            sidebar
+                .focus_history
+                .retain(|id| windows.iter().any(|w| w.id == *id));
+            changed |= sidebar.windows.len() != initial_len;
+        }
+        changed
+    }
+
+    /// Appends the sidebar of `from` onto the sidebar of `to`.
+    /// If `to` has no windows yet it also takes over the hidden and flipped flags.
+    pub fn move_sidebar(&mut self, from: u64, to: u64) {
+        if from == to {
+            return;
+        }
+        let Some(source) = self.sidebars.remove(&from) else {
+            return;
+        };
+        let target = self.sidebar_mut(to);
+        if target.windows.is_empty() {
+            target.is_hidden = source.is_hidden;
+            target.is_flipped = source.is_flipped;
+        }
+        target.windows.extend(source.windows);
+        target.focus_history.extend(source.focus_history);
+    }
+
+    /// Merges the sidebars of the workspaces for which `merge` returns true into the sidebar of `workspace`
+    pub fn merge_into(&mut self, workspace: u64, mut merge: impl FnMut(u64) -> bool) {
+        let others: Vec<u64> = self
+            .sidebars
+            .keys()
+            .copied()
+            .filter(|ws| *ws != workspace && merge(*ws))
+            .collect();
+        for ws in others {
+            self.move_sidebar(ws, workspace);
+        }
+    }
 }
 
-pub fn get_default_cache_dir() -> Result<PathBuf> {
-    let mut path = dirs::cache_dir().context("Could not find cache directory")?;
-    path.push("niri-sidebar");
+/// The directory holding the state file, lock and control socket: `dir` if given,
+/// `niri-sidebar` in the cache directory otherwise. Created if it does not exist.
+pub fn get_state_dir(dir: Option<PathBuf>) -> Result<PathBuf> {
+    let path = match dir {
+        Some(dir) => dir,
+        None => dirs::cache_dir()
+            .context("Could not find cache directory")?
+            .join("niri-sidebar"),
+    };
     if !path.exists() {
-        fs::create_dir_all(&path)?;
+        fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create state directory {}", path.display()))?;
     }
     Ok(path)
 }
 
+/// Blocks until this process holds the instance lock, released when the returned file is dropped
+pub fn lock_instance(base_dir: &Path) -> Result<LockFile> {
+    let mut path = base_dir.to_path_buf();
+    path.push("instance.lock");
+    let mut lock_file = LockFile::open(&path)?;
+    lock_file.lock()?;
+    Ok(lock_file)
+}
+
 pub fn load_state(base_dir: &Path) -> Result<AppState> {
     let mut path = base_dir.to_path_buf();
     path.push("state.json");
     if path.exists() {
         let content = fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&content).unwrap_or_default())
+        let mut state: AppState = serde_json::from_str(&content).unwrap_or_default();
+        if let Ok(legacy) = serde_json::from_str::<LegacyState>(&content) {
+            state.legacy_sidebar = Some(SidebarState {
+                windows: legacy.windows,
+                is_hidden: legacy.is_hidden,
+                is_flipped: legacy.is_flipped,
+                ..Default::default()
+            });
+        }
+        Ok(state)
     } else {
         Ok(AppState::default())
     }
@@ -66,6 +378,7 @@ mod tests {
             height: 400,
             is_floating: false,
             position: None,
+            identity: Default::default(),
         };
         let w2 = WindowState {
             id: 200,
@@ -73,14 +386,28 @@ mod tests {
             height: 1080,
             is_floating: true,
             position: Some((1.0, 2.0)),
+            identity: WindowIdentity {
+                app_id: Some("kitty".into()),
+                title: Some("Terminal".into()),
+                pid: Some(42),
+            },
         };
 
-        let original_state = AppState {
-            windows: vec![w1, w2],
+        let mut original_state = AppState {
             ignored_windows: vec![100, 200],
-            is_hidden: true,
-            is_flipped: true,
+            ..Default::default()
         };
+        original_state.sidebars.insert(
+            1,
+            SidebarState {
+                windows: vec![w1],
+                is_hidden: true,
+                is_flipped: true,
+                output: Some("eDP-1".into()),
+                focus_history: vec![100],
+            },
+        );
+        original_state.sidebar_mut(2).windows.push(w2);
 
         save_state(&original_state, temp_dir.path()).expect("Failed to save state");
         let loaded_state = load_state(temp_dir.path()).expect("Failed to load state");
@@ -95,21 +422,49 @@ mod tests {
     #[test]
     fn test_load_defaults_if_no_file() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let state = load_state(temp_dir.path()).expect("Should not fail on missing file");
         assert_eq!(state, AppState::default());
-        assert!(state.windows.is_empty());
+        assert!(state.sidebars.is_empty());
+    }
+
+    #[test]
+    fn test_migrates_single_sidebar_state() {
+        let temp_dir = tempdir().unwrap();
+        let legacy = r#"{
+            "windows": [
+                {"id": 100, "width": 500, "height": 400, "is_floating": true, "position": null},
+                {"id": 200, "width": 300, "height": 200, "is_floating": false, "position": [1.0, 2.0]}
+            ],
+            "ignored_windows": [7],
+            "is_hidden": true,
+            "is_flipped": false
+        }"#;
+        fs::write(temp_dir.path().join("state.json"), legacy).unwrap();
+
+        let mut state = load_state(temp_dir.path()).expect("Failed to load state");
+        assert_eq!(state.ignored_windows, vec![7]);
+        assert!(state.sidebars.is_empty());
+
+        // The old sidebar lands on the focused workspace
+        assert!(state.migrate_legacy(3));
+        let sidebar = state.sidebar(3).unwrap();
+        assert!(sidebar.is_hidden);
+        let ids: Vec<u64> = sidebar.windows.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![100, 200]);
+        assert_eq!(sidebar.windows[1].position, Some((1.0, 2.0)));
+        assert!(!state.migrate_legacy(3));
+
+        // Saved in the new format there is nothing left to migrate
+        save_state(&state, temp_dir.path()).unwrap();
+        let state = load_state(temp_dir.path()).expect("Failed to load state");
+        assert_eq!(state.legacy_sidebar, None);
+        assert_eq!(state.windows(3).len(), 2);
     }
 
     #[test]
     fn test_handles_corrupted_json() {
         let temp_dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("NIRI_SIDEBAR_TEST_DIR", temp_dir.path());
-        }
 
         let mut path = temp_dir.path().to_path_buf();
         path.push("state.json");
@@ -119,4 +474,124 @@ mod tests {
 
         assert_eq!(state, AppState::default());
     }
+
+    fn window(id: u64) -> WindowState {
+        WindowState {
+            id,
+            width: 100,
+            height: 100,
+            is_floating: false,
+            position: None,
+            identity: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_remove_window_from_any_sidebar() {
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(window(10));
+        state.sidebar_mut(2).windows.push(window(20));
+        state.sidebar_mut(2).windows.push(window(30));
+
+        assert_eq!(state.find_window(30), Some((2, 1)));
+        assert_eq!(state.remove_window(20).map(|w| w.id), Some(20));
+        assert_eq!(state.find_window(30), Some((2, 0)));
+        assert!(state.remove_window(99).is_none());
+        assert_eq!(state.windows(1).len(), 1);
+        assert!(state.windows(42).is_empty());
+    }
+
+    #[test]
+    fn test_merge_into_keeps_order_and_flags() {
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(window(10));
+        state.sidebar_mut(1).is_hidden = true;
+        state.sidebar_mut(2).windows.push(window(20));
+        state.sidebar_mut(4).windows.push(window(40));
+
+        state.merge_into(3, |ws| ws != 4);
+
+        assert_eq!(state.sidebars.len(), 2);
+        let ids: Vec<u64> = state.windows(3).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![10, 20]);
+        // Target was empty, so it takes over the flags of the first sidebar moved in
+        assert!(state.sidebar(3).unwrap().is_hidden);
+    }
+
+    #[test]
+    fn test_focus_history_follows_removals() {
+        let mut state = AppState::default();
+        for id in [10, 20, 30] {
+            state.sidebar_mut(1).windows.push(window(id));
+        }
+        let sidebar = state.sidebar_mut(1);
+        assert!(sidebar.record_focus(10));
+        assert!(sidebar.record_focus(20));
+        assert!(sidebar.record_focus(10));
+        assert!(!sidebar.record_focus(10));
+        sidebar.record_focus(30);
+        assert_eq!(sidebar.focus_history, vec![30, 10, 20]);
+
+        state.remove_window(30);
+        state.retain_windows(|w| w.id != 20);
+        assert_eq!(state.sidebar(1).unwrap().focus_history, vec![10]);
+        assert_eq!(state.sidebar(1).unwrap().last_focused(), Some(10));
+    }
+
+    #[test]
+    fn test_slots_count_from_the_visual_bottom() {
+        let mut sidebar = SidebarState {
+            windows: vec![window(10), window(20), window(30)],
+            ..Default::default()
+        };
+
+        assert_eq!(sidebar.slot_index(1), Some(0));
+        assert_eq!(sidebar.slot_index(0), None);
+        assert_eq!(sidebar.slot_index(4), None);
+        assert_eq!(sidebar.insert_index(Insert::Top), 3);
+        assert_eq!(sidebar.insert_index(Insert::Index(2)), 1);
+
+        // Flipped sidebars show the end of the list at the bottom
+        sidebar.is_flipped = true;
+        assert_eq!(sidebar.slot_index(1), Some(2));
+        assert_eq!(sidebar.slot_index(3), Some(0));
+        assert_eq!(sidebar.insert_index(Insert::Top), 0);
+        assert_eq!(sidebar.insert_index(Insert::Bottom), 3);
+        assert_eq!(sidebar.insert_index(Insert::Index(2)), 2);
+    }
+
+    #[test]
+    fn test_identity_match_score() {
+        use crate::test_utils::mock_window;
+
+        let window = mock_window(1, false, false, 1, None);
+        let identity = WindowIdentity::of(&window);
+        assert_eq!(identity.match_score(&window), Some(2));
+
+        // Same app after a restart: new pid, title unchanged
+        let mut restarted = window.clone();
+        restarted.pid = Some(999);
+        assert_eq!(identity.match_score(&restarted), Some(1));
+
+        let mut other_window = window.clone();
+        other_window.pid = Some(999);
+        other_window.title = Some("Other".into());
+        assert_eq!(identity.match_score(&other_window), None);
+
+        let mut other_app = window.clone();
+        other_app.app_id = Some("other".into());
+        assert_eq!(identity.match_score(&other_app), None);
+
+        // Without an app id there is nothing to recognize the window by
+        assert_eq!(WindowIdentity::default().match_score(&window), None);
+    }
+
+    #[test]
+    fn test_state_dir_is_created() {
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path().join("runtime/niri-sidebar");
+
+        assert_eq!(get_state_dir(Some(dir.clone())).unwrap(), dir);
+        assert!(dir.is_dir());
+    }
 }
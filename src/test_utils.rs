@@ -1,18 +1,26 @@
-use crate::config::{Geometry, Margins};
-use crate::{Config, NiriClient};
-use anyhow::Result;
+use crate::config::{Geometry, Margins, StackLayout};
+use crate::state::{AppState, WindowState};
+use crate::{Config, Ctx, NiriClient};
+use anyhow::{Context, Result};
 use niri_ipc::{Action, Response, Window, WindowLayout, Workspace};
+use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(Default, Debug, Clone)]
 pub struct MockNiri {
     pub windows: Vec<Window>,
+    pub workspaces: Vec<Workspace>,
+    pub outputs: HashMap<String, (i32, i32)>,
     pub sent_actions: Vec<Action>,
 }
 
 impl MockNiri {
+    /// A single 1920x1080 output "eDP-1" showing the focused workspace 1
     pub fn new(windows: Vec<Window>) -> Self {
         Self {
             windows,
+            workspaces: vec![mock_workspace(1, "eDP-1", true)],
+            outputs: HashMap::from([("eDP-1".to_string(), (1920, 1080))]),
             sent_actions: vec![],
         }
     }
@@ -36,21 +44,29 @@ impl NiriClient for MockNiri {
         Ok(Response::Handled)
     }
 
-    fn get_active_workspace(&mut self) -> Result<Workspace> {
-        Ok(Workspace {
-            id: 1,
-            idx: 0,
-            name: Some("test".into()),
-            output: Some("eDP-1".into()),
-            is_urgent: false,
-            is_active: true,
-            is_focused: true,
-            active_window_id: None,
-        })
+    fn get_workspaces(&mut self) -> Result<Vec<Workspace>> {
+        Ok(self.workspaces.clone())
     }
 
-    fn get_screen_dimensions(&mut self) -> Result<(i32, i32)> {
-        Ok((1920, 1080))
+    fn get_output_dimensions(&mut self, output: &str) -> Result<(i32, i32)> {
+        self.outputs
+            .get(output)
+            .copied()
+            .context("Output not found in mock")
+    }
+}
+
+/// An active workspace on `output`, focused if `is_focused` is set
+pub fn mock_workspace(id: u64, output: &str, is_focused: bool) -> Workspace {
+    Workspace {
+        id,
+        idx: 0,
+        name: Some("test".into()),
+        output: Some(output.into()),
+        is_urgent: false,
+        is_active: true,
+        is_focused,
+        active_window_id: None,
     }
 }
 
@@ -81,18 +97,45 @@ pub fn mock_window(
     }
 }
 
+/// The sidebar entry of a window that was tiled and as big as a `mock_window` before it joined
+pub fn mock_window_state(id: u64) -> WindowState {
+    WindowState {
+        id,
+        width: 1000,
+        height: 800,
+        is_floating: false,
+        position: None,
+        identity: Default::default(),
+    }
+}
+
+/// A context with `state` and `mock_config`, talking to a `MockNiri` that has `windows`
+pub fn mock_ctx(cache_dir: &Path, state: AppState, windows: Vec<Window>) -> Ctx<MockNiri> {
+    Ctx {
+        state,
+        config: mock_config(),
+        socket: MockNiri::new(windows),
+        cache_dir: cache_dir.to_path_buf(),
+    }
+}
+
 pub fn mock_config() -> Config {
     Config {
         geometry: Geometry {
-            width: 300,
-            height: 200,
-            gap: 10,
+            width: 300.into(),
+            height: 200.into(),
+            gap: 10.into(),
+            layout: StackLayout::Stack,
+            columns: 2,
+            accordion: false,
+            collapsed: 40.into(),
+            align: None,
         },
         margins: Margins {
-            top: 50,
-            right: 20,
-            left: 10,
-            bottom: 50,
+            top: 50.into(),
+            right: 20.into(),
+            left: 10.into(),
+            bottom: 50.into(),
         },
         ..Default::default()
     }
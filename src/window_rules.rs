@@ -1,82 +1,268 @@
-use niri_ipc::Window;
+use niri_ipc::{Window, Workspace};
+use regex::Regex;
+use serde::Serialize;
+use std::fmt::Display;
 
-use crate::config::WindowRule;
+use crate::config::{Insert, Size, WindowMatch, WindowRule};
+use crate::state::AppState;
 
-fn matches_window(app_id: &Option<String>, title: &Option<String>, rule: &WindowRule) -> bool {
-    let app_ok = match (&rule.app_id, app_id) {
-        (None, _) => true,
-        (Some(re), Some(id)) => re.is_match(id),
-        (Some(_), None) => false,
-    };
+/// A window together with everything window rules can match on
+#[derive(Debug, Clone, Copy)]
+pub struct RuleTarget<'a> {
+    pub window: &'a Window,
+    pub workspace: Option<&'a Workspace>,
+    /// Whether the window was floating before it joined the sidebar
+    pub is_floating: bool,
+}
 
-    let title_ok = match (&rule.title, title) {
-        (None, _) => true,
-        (Some(re), Some(title)) => re.is_match(title),
-        (Some(_), None) => false,
-    };
+impl<'a> RuleTarget<'a> {
+    /// Looks up the workspace of `window` and, for sidebar windows, the floating
+    /// state they had before they were added
+    pub fn new(window: &'a Window, workspaces: &'a [Workspace], state: &AppState) -> Self {
+        let is_floating = state
+            .find_window(window.id)
+            .map_or(window.is_floating, |(ws, index)| {
+                state.windows(ws)[index].is_floating
+            });
 
-    title_ok && app_ok
+        RuleTarget {
+            window,
+            workspace: workspaces
+                .iter()
+                .find(|ws| Some(ws.id) == window.workspace_id),
+            is_floating,
+        }
+    }
 }
 
-pub fn resolve_window_size(
-    rules: &[WindowRule],
-    window: &Window,
-    default_w: i32,
-    default_h: i32,
-) -> (i32, i32) {
-    for rule in rules {
-        if matches_window(&window.app_id, &window.title, rule) {
-            return (
-                rule.width.unwrap_or(default_w),
-                rule.height.unwrap_or(default_h),
-            );
+/// The outcome of one condition of a window rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub passed: bool,
+    pub reason: String,
+}
+
+fn check_regex(name: &str, regex: &Option<Regex>, value: Option<&str>) -> Option<Check> {
+    let re = regex.as_ref()?;
+    Some(match value {
+        Some(value) => {
+            let passed = re.is_match(value);
+            let verb = if passed { "matches" } else { "does not match" };
+            Check {
+                passed,
+                reason: format!("{} {:?} {} /{}/", name, value, verb, re),
+            }
         }
+        None => Check {
+            passed: false,
+            reason: format!("{} is unknown, can not match /{}/", name, re),
+        },
+    })
+}
+
+fn check_value<T: PartialEq + Display>(
+    name: &str,
+    expected: Option<T>,
+    actual: Option<T>,
+) -> Option<Check> {
+    let expected = expected?;
+    Some(match actual {
+        Some(actual) => Check {
+            passed: actual == expected,
+            reason: format!("{} is {}, expected {}", name, actual, expected),
+        },
+        None => Check {
+            passed: false,
+            reason: format!("{} is unknown, expected {}", name, expected),
+        },
+    })
+}
+
+impl WindowMatch {
+    /// Checks every condition that is set against `target`
+    pub fn explain(&self, target: &RuleTarget) -> Vec<Check> {
+        let window = target.window;
+        let workspace = target.workspace;
+
+        [
+            check_regex("app_id", &self.app_id, window.app_id.as_deref()),
+            check_regex("title", &self.title, window.title.as_deref()),
+            check_regex(
+                "workspace",
+                &self.workspace,
+                workspace.and_then(|ws| ws.name.as_deref()),
+            ),
+            check_value(
+                "workspace_index",
+                self.workspace_index,
+                workspace.map(|ws| ws.idx),
+            ),
+            check_regex(
+                "output",
+                &self.output,
+                workspace.and_then(|ws| ws.output.as_deref()),
+            ),
+            check_value("is_floating", self.is_floating, Some(target.is_floating)),
+            check_value("is_urgent", self.is_urgent, Some(window.is_urgent)),
+            check_value("pid", self.pid, window.pid),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn matches(&self, target: &RuleTarget) -> bool {
+        self.explain(target).iter().all(|check| check.passed)
     }
-    (default_w, default_h)
 }
 
-pub fn resolve_rule_peek(rules: &[WindowRule], window: &Window, default_peek: i32) -> i32 {
-    for rule in rules {
-        if matches_window(&window.app_id, &window.title, rule) {
-            return rule.peek.unwrap_or(default_peek);
+impl WindowRule {
+    /// Checks the conditions of the rule and every `exclude` against `target`,
+    /// the rule applies if all checks passed
+    pub fn explain(&self, target: &RuleTarget) -> Vec<Check> {
+        let mut checks = self.matches.explain(target);
+        for (index, exclude) in self.exclude.iter().enumerate() {
+            let exclude_checks = exclude.explain(target);
+            if exclude_checks.iter().all(|check| check.passed) {
+                let reasons: Vec<_> = exclude_checks.into_iter().map(|c| c.reason).collect();
+                checks.push(Check {
+                    passed: false,
+                    reason: format!("excluded by exclude {} ({})", index + 1, reasons.join(", ")),
+                });
+            }
         }
+        checks
+    }
+
+    pub fn matches(&self, target: &RuleTarget) -> bool {
+        self.matches.matches(target) && !self.exclude.iter().any(|m| m.matches(target))
     }
-    default_peek
 }
 
-pub fn resolve_rule_focus_peek(
-    rules: &[WindowRule],
-    window: &Window,
-    default_focus_peek: i32,
-) -> i32 {
-    for rule in rules {
-        if matches_window(&window.app_id, &window.title, rule) {
-            return rule.focus_peek.unwrap_or(default_focus_peek);
+/// All window rules matching one window merged together.
+/// Rules are applied in order, so later rules override the fields they set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectiveRule {
+    pub width: Option<Size>,
+    pub height: Option<Size>,
+    pub peek: Option<Size>,
+    pub focus_peek: Option<Size>,
+    pub auto_add: Option<bool>,
+    pub insert: Option<Insert>,
+    pub auto_remove: Option<bool>,
+    pub remove_when_tiled: Option<bool>,
+    pub remove_when_fullscreen: Option<bool>,
+}
+
+/// Why a window is pushed out of the sidebar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eject {
+    /// A rule with `auto_remove` applies to it
+    Rule,
+    Tiled,
+    Fullscreen,
+}
+
+impl EffectiveRule {
+    fn merge(&mut self, rule: &WindowRule) {
+        self.width = rule.width.or(self.width);
+        self.height = rule.height.or(self.height);
+        self.peek = rule.peek.or(self.peek);
+        self.focus_peek = rule.focus_peek.or(self.focus_peek);
+        self.auto_add = rule.auto_add.or(self.auto_add);
+        self.insert = rule.insert.or(self.insert);
+        self.auto_remove = rule.auto_remove.or(self.auto_remove);
+        self.remove_when_tiled = rule.remove_when_tiled.or(self.remove_when_tiled);
+        self.remove_when_fullscreen = rule.remove_when_fullscreen.or(self.remove_when_fullscreen);
+    }
+
+    /// The window size in pixels, relative sizes are taken from the `screen` size
+    pub fn size(
+        &self,
+        default_w: Size,
+        default_h: Size,
+        (screen_w, screen_h): (i32, i32),
+    ) -> (i32, i32) {
+        (
+            self.width.unwrap_or(default_w).resolve(screen_w),
+            self.height.unwrap_or(default_h).resolve(screen_h),
+        )
+    }
+
+    /// The peek in pixels, relative peeks are taken from `total`,
+    /// the screen size across the sidebar
+    pub fn peek(&self, default_peek: Size, total: i32) -> i32 {
+        self.peek.unwrap_or(default_peek).resolve(total)
+    }
+
+    pub fn focus_peek(&self, default_focus_peek: Size, total: i32) -> i32 {
+        self.focus_peek.unwrap_or(default_focus_peek).resolve(total)
+    }
+
+    /// Windows `auto_remove` applies to are never added automatically
+    pub fn auto_add(&self) -> bool {
+        self.auto_add.unwrap_or(false) && !self.auto_remove()
+    }
+
+    pub fn insert(&self, default_insert: Insert) -> Insert {
+        self.insert.unwrap_or(default_insert)
+    }
+
+    pub fn auto_remove(&self) -> bool {
+        self.auto_remove.unwrap_or(false)
+    }
+
+    /// Whether a sidebar window has to leave the sidebar, given its current state
+    /// and the size of its output
+    pub fn eject(&self, window: &Window, (screen_w, screen_h): (i32, i32)) -> Option<Eject> {
+        let (width, height) = window.layout.window_size;
+        if self.auto_remove() {
+            Some(Eject::Rule)
+        } else if self.remove_when_tiled.unwrap_or(false) && !window.is_floating {
+            Some(Eject::Tiled)
+        } else if self.remove_when_fullscreen.unwrap_or(false)
+            && width >= screen_w
+            && height >= screen_h
+        {
+            Some(Eject::Fullscreen)
+        } else {
+            None
         }
     }
-    default_focus_peek
 }
 
-pub fn resolve_auto_add(rules: &[WindowRule], window: &Window) -> bool {
+pub fn resolve_rule(rules: &[WindowRule], target: &RuleTarget) -> EffectiveRule {
+    let mut effective = EffectiveRule::default();
     for rule in rules {
-        if matches_window(&window.app_id, &window.title, rule) {
-            return rule.auto_add;
+        if rule.matches(target) {
+            effective.merge(rule);
         }
     }
-    false
+    effective
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_utils::mock_window;
+    use crate::state::WindowState;
+    use crate::test_utils::{mock_window, mock_window_state, mock_workspace};
     use regex::Regex;
 
+    const SCREEN: (i32, i32) = (1920, 1080);
+
+    fn target(window: &Window) -> RuleTarget<'_> {
+        RuleTarget {
+            window,
+            workspace: None,
+            is_floating: window.is_floating,
+        }
+    }
+
     #[test]
     fn test_resolve_window_size_defaults() {
         let rules = vec![];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let (w, h) = resolve_window_size(&rules, &window, 100, 200);
+        let (w, h) = resolve_rule(&rules, &target(&window)).size(100.into(), 200.into(), SCREEN);
         assert_eq!(w, 100);
         assert_eq!(h, 200);
     }
@@ -84,13 +270,16 @@ mod tests {
     #[test]
     fn test_resolve_window_size_match_app_id() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("test").unwrap()),
-            width: Some(500),
-            height: Some(600),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            width: Some(500.into()),
+            height: Some(600.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0))); // mock_window has app_id "test"
-        let (w, h) = resolve_window_size(&rules, &window, 100, 200);
+        let (w, h) = resolve_rule(&rules, &target(&window)).size(100.into(), 200.into(), SCREEN);
         assert_eq!(w, 500);
         assert_eq!(h, 600);
     }
@@ -98,13 +287,16 @@ mod tests {
     #[test]
     fn test_resolve_window_size_match_title() {
         let rules = vec![WindowRule {
-            title: Some(Regex::new("Test Window").unwrap()),
-            width: Some(800),
-            height: Some(900),
+            matches: WindowMatch {
+                title: Some(Regex::new("Test Window").unwrap()),
+                ..Default::default()
+            },
+            width: Some(800.into()),
+            height: Some(900.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0))); // mock_window has title "Test Window"
-        let (w, h) = resolve_window_size(&rules, &window, 100, 200);
+        let (w, h) = resolve_rule(&rules, &target(&window)).size(100.into(), 200.into(), SCREEN);
         assert_eq!(w, 800);
         assert_eq!(h, 900);
     }
@@ -112,86 +304,329 @@ mod tests {
     #[test]
     fn test_resolve_window_size_no_match() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("nomatch").unwrap()),
-            width: Some(500),
-            height: Some(600),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("nomatch").unwrap()),
+                ..Default::default()
+            },
+            width: Some(500.into()),
+            height: Some(600.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let (w, h) = resolve_window_size(&rules, &window, 100, 200);
+        let (w, h) = resolve_rule(&rules, &target(&window)).size(100.into(), 200.into(), SCREEN);
         assert_eq!(w, 100);
         assert_eq!(h, 200);
     }
 
+    #[test]
+    fn test_resolve_window_size_relative_to_screen() {
+        let rules = vec![WindowRule {
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            height: Some(Size::Fraction(0.5)),
+            ..Default::default()
+        }];
+        let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
+        let (w, h) =
+            resolve_rule(&rules, &target(&window)).size(Size::Fraction(0.25), 200.into(), SCREEN);
+        assert_eq!(w, 480);
+        assert_eq!(h, 540);
+    }
+
     #[test]
     fn test_resolve_rule_peek_match() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("test").unwrap()),
-            peek: Some(50),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            peek: Some(50.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let peek = resolve_rule_peek(&rules, &window, 10);
+        let peek = resolve_rule(&rules, &target(&window)).peek(10.into(), 1920);
         assert_eq!(peek, 50);
     }
 
     #[test]
     fn test_resolve_rule_peek_default() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("nomatch").unwrap()),
-            peek: Some(50),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("nomatch").unwrap()),
+                ..Default::default()
+            },
+            peek: Some(50.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let peek = resolve_rule_peek(&rules, &window, 10);
+        let peek = resolve_rule(&rules, &target(&window)).peek(10.into(), 1920);
         assert_eq!(peek, 10);
     }
 
     #[test]
     fn test_resolve_rule_focus_peek_match() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("test").unwrap()),
-            focus_peek: Some(70),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            focus_peek: Some(70.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let peek = resolve_rule_focus_peek(&rules, &window, 20);
+        let peek = resolve_rule(&rules, &target(&window)).focus_peek(20.into(), 1920);
         assert_eq!(peek, 70);
     }
 
     #[test]
     fn test_resolve_rule_focus_peek_default() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("nomatch").unwrap()),
-            focus_peek: Some(70),
+            matches: WindowMatch {
+                app_id: Some(Regex::new("nomatch").unwrap()),
+                ..Default::default()
+            },
+            focus_peek: Some(70.into()),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let peek = resolve_rule_focus_peek(&rules, &window, 20);
+        let peek = resolve_rule(&rules, &target(&window)).focus_peek(20.into(), 1920);
         assert_eq!(peek, 20);
     }
 
     #[test]
     fn test_resolve_auto_add_match() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("test").unwrap()),
-            auto_add: true,
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            auto_add: Some(true),
             ..Default::default()
         }];
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let auto_add = resolve_auto_add(&rules, &window);
-        assert!(auto_add);
+        assert!(resolve_rule(&rules, &target(&window)).auto_add());
     }
 
     #[test]
     fn test_resolve_auto_add_default_false() {
         let rules = vec![WindowRule {
-            app_id: Some(Regex::new("nomatch").unwrap()),
-            auto_add: true,
+            matches: WindowMatch {
+                app_id: Some(Regex::new("nomatch").unwrap()),
+                ..Default::default()
+            },
+            auto_add: Some(true),
+            ..Default::default()
+        }];
+        let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
+        assert!(!resolve_rule(&rules, &target(&window)).auto_add());
+    }
+
+    #[test]
+    fn test_later_rules_override_only_the_fields_they_set() {
+        let rules = vec![
+            // Broad rule
+            WindowRule {
+                matches: WindowMatch {
+                    app_id: Some(Regex::new("test").unwrap()),
+                    ..Default::default()
+                },
+                width: Some(500.into()),
+                peek: Some(20.into()),
+                auto_add: Some(true),
+                ..Default::default()
+            },
+            // More specific rule
+            WindowRule {
+                matches: WindowMatch {
+                    title: Some(Regex::new("^Test").unwrap()),
+                    ..Default::default()
+                },
+                peek: Some(50.into()),
+                auto_add: Some(false),
+                ..Default::default()
+            },
+            // Does not match, contributes nothing
+            WindowRule {
+                matches: WindowMatch {
+                    app_id: Some(Regex::new("nomatch").unwrap()),
+                    ..Default::default()
+                },
+                height: Some(900.into()),
+                ..Default::default()
+            },
+        ];
+        let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
+
+        assert_eq!(
+            resolve_rule(&rules, &target(&window)),
+            EffectiveRule {
+                width: Some(500.into()),
+                height: None,
+                peek: Some(50.into()),
+                focus_peek: None,
+                auto_add: Some(false),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_match_workspace_output_and_window_state() {
+        let mut window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
+        window.is_urgent = true;
+        let mut workspace = mock_workspace(1, "DP-1", true);
+        workspace.name = Some("chat".into());
+        workspace.idx = 3;
+        let workspaces = vec![workspace];
+        let target = RuleTarget::new(&window, &workspaces, &AppState::default());
+
+        let matcher = WindowMatch {
+            workspace: Some(Regex::new("^chat$").unwrap()),
+            workspace_index: Some(3),
+            output: Some(Regex::new("^DP-").unwrap()),
+            is_floating: Some(false),
+            is_urgent: Some(true),
+            pid: Some(123),
+            ..Default::default()
+        };
+        assert!(matcher.matches(&target));
+
+        for mismatch in [
+            WindowMatch {
+                workspace: Some(Regex::new("^web$").unwrap()),
+                ..Default::default()
+            },
+            WindowMatch {
+                workspace_index: Some(1),
+                ..Default::default()
+            },
+            WindowMatch {
+                output: Some(Regex::new("^eDP-1$").unwrap()),
+                ..Default::default()
+            },
+            WindowMatch {
+                is_floating: Some(true),
+                ..Default::default()
+            },
+            WindowMatch {
+                is_urgent: Some(false),
+                ..Default::default()
+            },
+            WindowMatch {
+                pid: Some(1),
+                ..Default::default()
+            },
+        ] {
+            assert!(
+                !mismatch.matches(&target),
+                "{:?} should not match",
+                mismatch
+            );
+        }
+
+        // Without a known workspace, workspace conditions can not hold
+        assert!(
+            !WindowMatch {
+                workspace_index: Some(3),
+                ..Default::default()
+            }
+            .matches(&RuleTarget::new(&window, &[], &AppState::default()))
+        );
+    }
+
+    #[test]
+    fn test_match_uses_floating_state_from_before_the_sidebar() {
+        // Tiled window that was added to the sidebar, so it is floating now
+        let window = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        let mut state = AppState::default();
+        state.sidebar_mut(1).windows.push(WindowState {
+            width: 300,
+            height: 200,
+            ..mock_window_state(1)
+        });
+
+        let matcher = WindowMatch {
+            is_floating: Some(false),
+            ..Default::default()
+        };
+        assert!(matcher.matches(&RuleTarget::new(&window, &[], &state)));
+        assert!(!matcher.matches(&RuleTarget::new(&window, &[], &AppState::default())));
+    }
+
+    #[test]
+    fn test_exclude_skips_rule() {
+        let rules = vec![WindowRule {
+            matches: WindowMatch {
+                app_id: Some(Regex::new("test").unwrap()),
+                ..Default::default()
+            },
+            exclude: vec![WindowMatch {
+                title: Some(Regex::new("^scratch$").unwrap()),
+                ..Default::default()
+            }],
+            auto_add: Some(true),
             ..Default::default()
         }];
+
         let window = mock_window(1, false, false, 1, Some((1.0, 2.0)));
-        let auto_add = resolve_auto_add(&rules, &window);
-        assert!(!auto_add);
+        assert!(resolve_rule(&rules, &target(&window)).auto_add());
+
+        let mut scratch = mock_window(2, false, false, 1, Some((1.0, 2.0)));
+        scratch.title = Some("scratch".into());
+        assert!(!resolve_rule(&rules, &target(&scratch)).auto_add());
+    }
+
+    #[test]
+    fn test_parse_rule_with_matchers_and_exclude() {
+        let rule: WindowRule = toml::from_str(
+            r#"
+            app_id = "kitty"
+            workspace_index = 2
+            is_floating = false
+            width = "30%"
+            exclude = [{ title = "^scratch$" }, { is_urgent = true }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rule.matches.app_id.unwrap().as_str(), "kitty");
+        assert_eq!(rule.matches.workspace_index, Some(2));
+        assert_eq!(rule.matches.is_floating, Some(false));
+        assert_eq!(rule.width, Some(Size::Fraction(0.3)));
+        assert_eq!(rule.exclude.len(), 2);
+        assert_eq!(rule.exclude[1].is_urgent, Some(true));
+    }
+
+    #[test]
+    fn test_eject_reasons() {
+        let screen = (1920, 1080);
+        let mut window = mock_window(1, false, true, 1, Some((1.0, 2.0)));
+        window.layout.window_size = (300, 200);
+
+        let rule = EffectiveRule {
+            remove_when_tiled: Some(true),
+            remove_when_fullscreen: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(rule.eject(&window, screen), None);
+
+        window.layout.window_size = (1920, 1080);
+        assert_eq!(rule.eject(&window, screen), Some(Eject::Fullscreen));
+
+        window.is_floating = false;
+        assert_eq!(rule.eject(&window, screen), Some(Eject::Tiled));
+
+        // Without the options neither state matters
+        assert_eq!(EffectiveRule::default().eject(&window, screen), None);
+
+        let remove = EffectiveRule {
+            auto_add: Some(true),
+            auto_remove: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(remove.eject(&window, screen), Some(Eject::Rule));
+        assert!(!remove.auto_add());
     }
 }